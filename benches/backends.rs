@@ -0,0 +1,67 @@
+//! Compares the stack and register backends on arithmetic-heavy expressions:
+//!
+//!     cargo bench --bench backends --features register-vm
+
+mod common;
+
+use common::{parse_unfolded, sum_of_products};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use kurisu::codegen;
+use kurisu::vm::VM;
+
+/// `((((1 + 2) * 3) - 4) ...)`
+fn nested(depth: usize) -> String {
+    let mut source = String::from("1");
+    for i in 0..depth {
+        let op = ["+", "*", "-", "/"][i % 4];
+        source = format!("({} {} {})", source, op, i + 2);
+    }
+    source
+}
+
+/// `1 < 2 == !(3 >= 4) != ...`
+fn comparisons(terms: usize) -> String {
+    let mut source = String::from("1 < 2");
+    for i in 0..terms {
+        source = format!("({}) == !({} >= {})", source, i, i + 1);
+    }
+    source
+}
+
+fn backends(c: &mut Criterion) {
+    let scripts = [
+        ("sum of products", sum_of_products(200)),
+        ("nested", nested(200)),
+        ("comparisons", comparisons(100)),
+    ];
+
+    for (name, source) in scripts.iter() {
+        let program = parse_unfolded(source);
+        let chunk = codegen::generate(&program);
+        let register_chunk =
+            codegen::registers::generate(&program).expect("benchmark script should compile");
+
+        println!(
+            "{}: {} stack instructions, {} register instructions",
+            name,
+            chunk.instruction_count(),
+            register_chunk.len()
+        );
+
+        let mut group = c.benchmark_group(*name);
+        let mut vm = VM::new();
+        group.bench_function("stack", |b| {
+            b.iter(|| vm.execute(black_box(chunk.clone())).unwrap())
+        });
+        group.bench_function("register", |b| {
+            b.iter(|| {
+                vm.execute_registers(black_box(register_chunk.clone()))
+                    .unwrap()
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, backends);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+//! Script generators shared by the benchmarks.
+//!
+//! Constant folding would reduce every generated script to a single constant, so the
+//! benchmarks generate code from the unfolded AST returned by `parse_unfolded`.
+
+use kurisu::ast::Stmt;
+use kurisu::parser;
+
+pub fn parse_unfolded(source: &str) -> Vec<Stmt> {
+    parser::parse(source).expect("benchmark script should parse")
+}
+
+/// `1 + 2 * 3 - 4 / 5 + 6 * 7 - ...`
+pub fn sum_of_products(terms: usize) -> String {
+    let mut source = String::from("1");
+    for i in 0..terms {
+        let op = if i % 2 == 0 { " + " } else { " - " };
+        source.push_str(&format!("{}{} * {} / {}", op, i, i + 1, i + 2));
+    }
+    source
+}
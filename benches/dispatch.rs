@@ -0,0 +1,50 @@
+//! Measures the stack VM's dispatch loop, compare two builds with
+//! `cargo bench --bench dispatch -- --save-baseline <name>` and `-- --baseline <name>`.
+
+mod common;
+
+use common::{parse_unfolded, sum_of_products};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use kurisu::codegen;
+use kurisu::peephole;
+use kurisu::vm::VM;
+
+/// `!(1 < 2) == (3 >= 4) != ...`
+fn comparisons(terms: usize) -> String {
+    let mut source = String::from("1 < 2");
+    for i in 0..terms {
+        source = format!("!({}) == ({} >= {})", source, i, i + 1);
+    }
+    source
+}
+
+/// `"a" + "b" + "c" + ...`
+fn concatenation(terms: usize) -> String {
+    let mut source = String::from("\"\"");
+    for i in 0..terms {
+        source.push_str(&format!(" + \"{}\"", i));
+    }
+    source
+}
+
+fn dispatch(c: &mut Criterion) {
+    let scripts = [
+        ("arithmetic", sum_of_products(500)),
+        ("comparisons", comparisons(200)),
+        ("concatenation", concatenation(200)),
+    ];
+
+    let mut vm = VM::new();
+    for (name, source) in scripts.iter() {
+        let program = parse_unfolded(source);
+        let mut chunk = codegen::generate(&program);
+        peephole::optimize(&mut chunk);
+
+        c.bench_function(name, |b| {
+            b.iter(|| vm.execute(black_box(chunk.clone())).unwrap())
+        });
+    }
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+//! Compares the two `Value` representations, run once per representation and let criterion
+//! report the difference:
+//!
+//!     cargo bench --bench value -- --save-baseline enum
+//!     cargo bench --bench value --features nan-boxing -- --baseline enum
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use kurisu::object::Object;
+use kurisu::value::Value;
+
+fn numbers(c: &mut Criterion) {
+    c.bench_function("sum numbers on a stack", |b| {
+        b.iter(|| {
+            let mut stack = vec![Value::number(0.0)];
+            for i in 0..1000 {
+                stack.push(Value::number(black_box(i as f64)));
+                let b = stack.pop().unwrap().to_number();
+                let a = stack.pop().unwrap().to_number();
+                stack.push(Value::number(a + b));
+            }
+            stack.pop().unwrap()
+        })
+    });
+}
+
+fn truthiness(c: &mut Criterion) {
+    let values: Vec<Value> = (0..1000)
+        .map(|i| match i % 4 {
+            0 => Value::nil(),
+            1 => Value::boolean(i % 3 == 0),
+            2 => Value::number(i as f64),
+            _ => Value::object(Object::String(i.to_string())),
+        })
+        .collect();
+
+    c.bench_function("is_falsey over mixed values", |b| {
+        b.iter(|| values.iter().filter(|v| black_box(v).is_falsey()).count())
+    });
+
+    c.bench_function("clone mixed values", |b| {
+        b.iter(|| black_box(&values).clone())
+    });
+}
+
+fn equality(c: &mut Criterion) {
+    let lhs: Vec<Value> = (0..1000).map(|i| Value::number(i as f64)).collect();
+    let rhs: Vec<Value> = (0..1000).map(|i| Value::number((i % 10) as f64)).collect();
+
+    c.bench_function("compare numbers", |b| {
+        b.iter(|| {
+            lhs.iter()
+                .zip(rhs.iter())
+                .filter(|(a, b)| black_box(a) == black_box(b))
+                .count()
+        })
+    });
+}
+
+criterion_group!(benches, numbers, truthiness, equality);
+criterion_main!(benches);
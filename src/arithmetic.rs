@@ -0,0 +1,262 @@
+//! Arithmetic and comparisons on numbers, shared by both backends and constant folding so they
+//! always agree.
+//!
+//! An operation on two ints gives an int, and fails rather than overflow. As soon as a float is
+//! involved the int is promoted to a float and so is the result. The exceptions are `/`, which
+//! always divides as floats, and `**` with a negative exponent, `~/` is the floored division that
+//! keeps ints as ints. Bitwise operators only work on ints.
+
+use crate::value::Value;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// The errors that depend on the operands' values rather than their types
+pub const OVERFLOW: &str = "Integer overflow";
+pub const DIVISION_BY_ZERO: &str = "Integer division by zero";
+pub const SHIFT_OUT_OF_RANGE: &str = "Shift amount must be between 0 and 63";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ArithOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    /// Division rounded down, `~/`
+    FloorDivide,
+    /// The remainder of `FloorDivide`, it has the sign of the divisor
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    /// Keeps the sign, as ints are signed
+    ShiftRight,
+}
+
+impl ArithOp {
+    fn is_bitwise(self) -> bool {
+        matches!(
+            self,
+            Self::BitAnd | Self::BitOr | Self::BitXor | Self::ShiftLeft | Self::ShiftRight
+        )
+    }
+}
+
+/// Writes a float so it can't be mistaken for an int, whole ones get a `.0`
+pub fn write_float(f: &mut fmt::Formatter<'_>, float: f64) -> fmt::Result {
+    if float.is_finite() && float.fract() == 0.0 {
+        write!(f, "{:.1}", float)
+    } else {
+        write!(f, "{}", float)
+    }
+}
+
+pub fn is_numeric(val: &Value) -> bool {
+    val.is_int() || val.is_number()
+}
+
+/// `val` as a float, ints are promoted
+fn to_float(val: &Value) -> f64 {
+    if val.is_int() {
+        val.as_int() as f64
+    } else {
+        val.to_number()
+    }
+}
+
+pub fn arithmetic(op: ArithOp, a: &Value, b: &Value) -> Result<Value, &'static str> {
+    if op.is_bitwise() {
+        if !(a.is_int() && b.is_int()) {
+            return Err("Operands must be integers");
+        }
+        return bitwise(op, a.as_int(), b.as_int()).map(Value::int);
+    }
+    if !(is_numeric(a) && is_numeric(b)) {
+        return Err(match op {
+            ArithOp::Add => "Operands must be two numbers or two strings",
+            _ => "Operands must be numbers",
+        });
+    }
+    let negative_power = op == ArithOp::Power && b.is_int() && b.as_int() < 0;
+    if a.is_int() && b.is_int() && op != ArithOp::Divide && !negative_power {
+        return int_arithmetic(op, a.as_int(), b.as_int()).map(Value::int);
+    }
+
+    let (a, b) = (to_float(a), to_float(b));
+    Ok(Value::number(match op {
+        ArithOp::Add => a + b,
+        ArithOp::Subtract => a - b,
+        ArithOp::Multiply => a * b,
+        ArithOp::Divide => a / b,
+        ArithOp::FloorDivide => (a / b).floor(),
+        ArithOp::Power => a.powf(b),
+        ArithOp::Modulo => {
+            let rem = a % b;
+            if rem != 0.0 && (rem < 0.0) != (b < 0.0) {
+                rem + b
+            } else {
+                rem
+            }
+        }
+        _ => unreachable!("bitwise operators are handled above"),
+    }))
+}
+
+fn int_arithmetic(op: ArithOp, a: i64, b: i64) -> Result<i64, &'static str> {
+    if b == 0 && matches!(op, ArithOp::FloorDivide | ArithOp::Modulo) {
+        return Err(DIVISION_BY_ZERO);
+    }
+    match op {
+        ArithOp::Add => a.checked_add(b).ok_or(OVERFLOW),
+        ArithOp::Subtract => a.checked_sub(b).ok_or(OVERFLOW),
+        ArithOp::Multiply => a.checked_mul(b).ok_or(OVERFLOW),
+        ArithOp::FloorDivide => {
+            let quotient = a.checked_div(b).ok_or(OVERFLOW)?;
+            // Rust rounds towards zero
+            if a % b != 0 && (a < 0) != (b < 0) {
+                Ok(quotient - 1)
+            } else {
+                Ok(quotient)
+            }
+        }
+        ArithOp::Modulo => {
+            // `i64::MIN % -1` overflows, even though the remainder is 0
+            let rem = a.wrapping_rem(b);
+            if rem != 0 && (rem < 0) != (b < 0) {
+                Ok(rem + b)
+            } else {
+                Ok(rem)
+            }
+        }
+        ArithOp::Power => u32::try_from(b)
+            .ok()
+            .and_then(|b| a.checked_pow(b))
+            .ok_or(OVERFLOW),
+        _ => unreachable!("not an int operation"),
+    }
+}
+
+fn bitwise(op: ArithOp, a: i64, b: i64) -> Result<i64, &'static str> {
+    let shift = || {
+        u32::try_from(b)
+            .ok()
+            .filter(|&b| b < i64::BITS)
+            .ok_or(SHIFT_OUT_OF_RANGE)
+    };
+    Ok(match op {
+        ArithOp::BitAnd => a & b,
+        ArithOp::BitOr => a | b,
+        ArithOp::BitXor => a ^ b,
+        ArithOp::ShiftLeft => a << shift()?,
+        ArithOp::ShiftRight => a >> shift()?,
+        _ => unreachable!("not a bitwise operator"),
+    })
+}
+
+pub fn negate(val: &Value) -> Result<Value, &'static str> {
+    if val.is_int() {
+        val.as_int().checked_neg().map(Value::int).ok_or(OVERFLOW)
+    } else if val.is_number() {
+        Ok(Value::number(-val.to_number()))
+    } else {
+        Err("Operand must be a number")
+    }
+}
+
+/// `~val`, which flips every bit of an int
+pub fn bit_not(val: &Value) -> Result<Value, &'static str> {
+    if val.is_int() {
+        Ok(Value::int(!val.as_int()))
+    } else {
+        Err("Operand must be an integer")
+    }
+}
+
+/// Orders two numbers, `None` if either is a NaN. Ints are compared exactly rather than as floats
+pub fn compare(a: &Value, b: &Value) -> Result<Option<Ordering>, &'static str> {
+    if !(is_numeric(a) && is_numeric(b)) {
+        return Err("Operands must be numbers");
+    }
+    Ok(match (a.is_int(), b.is_int()) {
+        (true, true) => Some(a.as_int().cmp(&b.as_int())),
+        (true, false) => compare_int_float(a.as_int(), b.to_number()),
+        (false, true) => compare_int_float(b.as_int(), a.to_number()).map(Ordering::reverse),
+        (false, false) => a.to_number().partial_cmp(&b.to_number()),
+    })
+}
+
+fn compare_int_float(int: i64, float: f64) -> Option<Ordering> {
+    if float.is_nan() {
+        return None;
+    }
+    // Like `int_equals_float`, whole floats convert to i128 exactly while ints may be rounded
+    // as floats. Floats beyond i128 saturate, which keeps them beyond every int
+    let whole = float.trunc();
+    match (int as i128).cmp(&(whole as i128)) {
+        Ordering::Equal => 0.0.partial_cmp(&(float - whole)),
+        ord => Some(ord),
+    }
+}
+
+/// `==` in the language, an int equals a float with exactly the same value
+pub fn equal(a: &Value, b: &Value) -> bool {
+    match (a.is_int(), b.is_int()) {
+        (true, false) if b.is_number() => int_equals_float(a.as_int(), b.to_number()),
+        (false, true) if a.is_number() => int_equals_float(b.as_int(), a.to_number()),
+        _ => a == b,
+    }
+}
+
+fn int_equals_float(int: i64, float: f64) -> bool {
+    // Both conversions are needed, as large ints are rounded when made into floats
+    int as f64 == float && float as i128 == int as i128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ordering(a: Value, b: Value) -> Option<Ordering> {
+        compare(&a, &b).unwrap()
+    }
+
+    #[test]
+    fn whole_floats_are_shown_unlike_ints() {
+        let shown = |val: Value| val.to_string();
+        assert_eq!(shown(Value::number(2.0)), "2.0");
+        assert_eq!(shown(Value::number(-0.0)), "-0.0");
+        assert_eq!(shown(Value::number(1e21)), "1000000000000000000000.0");
+        assert_eq!(shown(Value::number(2.5)), "2.5");
+        assert_eq!(shown(Value::number(f64::INFINITY)), "inf");
+        assert_eq!(shown(Value::number(f64::NAN)), "NaN");
+        assert_eq!(shown(Value::int(2)), "2");
+    }
+
+    #[test]
+    fn ints_and_floats_are_ordered_exactly() {
+        let (int, float) = (Value::int, Value::number);
+        assert_eq!(
+            ordering(int(9007199254740993), float(9007199254740992.0)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            ordering(float(9007199254740992.0), int(9007199254740993)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(ordering(int(2), float(2.0)), Some(Ordering::Equal));
+        assert_eq!(ordering(int(2), float(2.5)), Some(Ordering::Less));
+        assert_eq!(ordering(int(-2), float(-2.5)), Some(Ordering::Greater));
+        assert_eq!(ordering(int(i64::MAX), float(9.3e18)), Some(Ordering::Less));
+        assert_eq!(
+            ordering(int(i64::MIN), float(-9.3e18)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            ordering(int(i64::MAX), float(f64::INFINITY)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(ordering(int(0), float(f64::NAN)), None);
+    }
+}
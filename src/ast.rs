@@ -0,0 +1,126 @@
+use crate::scanner::Token;
+use crate::value::Value;
+
+/// The region of source code a node was parsed from
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Span {
+    pub start: usize, // Offset of the first byte in the source
+    pub end: usize,   // Offset one past the last byte
+    pub line: usize,  // The line on which the span starts
+}
+
+impl Span {
+    /// Returns a span going from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+            line: self.line,
+        }
+    }
+}
+
+impl From<&Token> for Span {
+    fn from(token: &Token) -> Self {
+        Span {
+            start: token.start,
+            end: token.end,
+            line: token.line,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    /// Only produced by string interpolation, there's no syntax for it
+    ToString,
+    /// `~`
+    BitNot,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    /// `~/`
+    FloorDivide,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Literal(Value),
+    /// Reads the global with the given name
+    Variable(String),
+    Assign {
+        name: String,
+        value: Box<Expr>,
+    },
+    Grouping(Box<Expr>),
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    /// `target[index]`
+    Index {
+        target: Box<Expr>,
+        index: Box<Expr>,
+    },
+    Unary {
+        op: UnaryOp,
+        op_span: Span,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        op_span: Span,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+    /// The `///` comments right before the statement, without the slashes
+    pub doc: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
+    Expression(Expr),
+    /// Declares a global, which is `nil` if there's no initializer
+    Var {
+        name: String,
+        initializer: Option<Expr>,
+    },
+}
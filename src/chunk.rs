@@ -1,19 +1,102 @@
+use crate::object::Object;
 use crate::opcode::OpCode;
 use crate::value::Value;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::ops::Index;
+
+/// Identifies a constant for deduplication purposes, numbers are compared
+/// by their bit pattern so that `0.0`/`-0.0` stay distinct and `NaN`s can be shared
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Nil,
+    Boolean(bool),
+    Number(u64),
+    Int(i64),
+    String(String),
+}
+
+impl ConstantKey {
+    fn new(val: &Value) -> Self {
+        if val.is_nil() {
+            Self::Nil
+        } else if val.is_bool() {
+            Self::Boolean(val.as_bool())
+        } else if val.is_number() {
+            Self::Number(val.to_number().to_bits())
+        } else if val.is_int() {
+            Self::Int(val.as_int())
+        } else {
+            match val.as_object() {
+                Object::String(stri) => Self::String(stri.clone()),
+                _ => unreachable!("only strings are constants"),
+            }
+        }
+    }
+}
+
+/// The constants used by a chunk, identical constants are only stored once
+#[derive(Clone, Default)]
+pub struct ConstantPool {
+    values: Vec<Value>,
+    indices: HashMap<ConstantKey, u32>,
+}
+
+impl ConstantPool {
+    /// Returns the index of `val` in the pool, adding it only if an identical
+    /// constant isn't already present
+    pub fn add(&mut self, val: Value) -> u32 {
+        let key = ConstantKey::new(&val);
+        if let Some(&idx) = self.indices.get(&key) {
+            return idx;
+        }
+
+        self.values.push(val);
+        let idx = (self.values.len() - 1) as u32;
+        self.indices.insert(key, idx);
+        idx
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl Index<usize> for ConstantPool {
+    type Output = Value;
+
+    fn index(&self, idx: usize) -> &Value {
+        &self.values[idx]
+    }
+}
 
 #[derive(Clone)]
 pub struct Chunk {
     code: Vec<u8>,
-    constants: Vec<Value>,
+    constants: ConstantPool,
     pub lines: Vec<u32>,
+    /// Whether the returned value is that of a trailing expression statement, rather than
+    /// an implicit `nil`
+    pub returns_expression: bool,
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chunk {
     pub fn new() -> Self {
         Chunk {
             code: Vec::new(),
-            constants: Vec::new(),
+            constants: ConstantPool::default(),
             lines: Vec::new(),
+            returns_expression: false,
         }
     }
 
@@ -22,23 +105,36 @@ impl Chunk {
         self.lines.push(line);
     }
 
+    pub fn add_constant(&mut self, val: Value) -> u32 {
+        self.constants.add(val)
+    }
+
     pub fn append_constant(&mut self, val: Value, line: u32) {
-        self.constants.push(val);
-        let len = (self.constants.len() - 1) as u32;
-        if len < 256 {
+        let idx = self.add_constant(val);
+        if idx < 256 {
             self.append(OpCode::Constant as u8, line);
-            self.append(len as u8, line);
+            self.append(idx as u8, line);
         } else {
             // In this case we emit OpCode::ConstantLong which has a 4 byte operand
-
-            self.append(OpCode::ConstantLong as u8, line);
-            self.append(((len & 0xff_00_00_00) >> 24) as u8, line);
-            self.append(((len & 0x00_ff_00_00) >> 16) as u8, line);
-            self.append(((len & 0x00_00_ff_00) >> 8) as u8, line);
-            self.append((len & 0x00_00_00_ff) as u8, line);
+            self.append_long(OpCode::ConstantLong, idx, line);
         }
     }
 
+    /// Appends an instruction accessing the global called `name`
+    pub fn append_global(&mut self, op: OpCode, name: &str, line: u32) {
+        let idx = self.add_constant(Value::object(Object::String(name.to_string())));
+        self.append_long(op, idx, line);
+    }
+
+    /// Appends `op` followed by its 4 byte operand
+    fn append_long(&mut self, op: OpCode, operand: u32, line: u32) {
+        self.append(op as u8, line);
+        self.append(((operand & 0xff_00_00_00) >> 24) as u8, line);
+        self.append(((operand & 0x00_ff_00_00) >> 16) as u8, line);
+        self.append(((operand & 0x00_00_ff_00) >> 8) as u8, line);
+        self.append((operand & 0x00_00_00_ff) as u8, line);
+    }
+
     pub fn next_byte(&self, ip: &mut usize) -> u8 {
         let byte = self.code[*ip];
         *ip += 1;
@@ -61,75 +157,140 @@ impl Chunk {
         &self.constants[idx]
     }
 
+    pub fn constants(&self) -> &ConstantPool {
+        &self.constants
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    /// Replaces the chunk's code, `lines` must have an entry for every byte of `code`
+    pub fn set_code(&mut self, code: Vec<u8>, lines: Vec<u32>) {
+        debug_assert_eq!(code.len(), lines.len());
+        self.code = code;
+        self.lines = lines;
+    }
+
     pub fn len(&self) -> usize {
         self.code.len()
     }
 
-    pub fn disassemble(&self, name: &str) {
-        println!("-== {} ==-", name);
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// The number of instructions in the chunk, as opposed to its length in bytes
+    pub fn instruction_count(&self) -> usize {
+        let mut count = 0;
+        let mut offset = 0;
+        while offset < self.code.len() {
+            offset += OpCode::from(self.code[offset]).size();
+            count += 1;
+        }
+        count
+    }
+
+    pub fn disassemble(&self, name: &str, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "-== {} ==-", name)?;
 
         let mut offset = 0usize;
         while offset < self.code.len() {
-            offset = self.dissassemble_instruction(offset);
+            offset = self.dissassemble_instruction(offset, out)?;
         }
+        Ok(())
     }
 
-    pub fn dissassemble_instruction(&self, offset: usize) -> usize {
-        print!("o:{:04} ", offset);
+    pub fn dissassemble_instruction(
+        &self,
+        offset: usize,
+        out: &mut dyn Write,
+    ) -> io::Result<usize> {
+        write!(out, "o:{:04} ", offset)?;
 
         if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
-            print!("     | ")
+            write!(out, "     | ")?;
         } else {
-            print!("l:{:04} ", self.lines[offset])
+            write!(out, "l:{:04} ", self.lines[offset])?;
         }
 
         let op: OpCode = self.code[offset].into();
         match op {
             OpCode::Unknown => {
-                println!("Unknown opcode ({})", self.code[offset]);
-                offset + 1
+                writeln!(out, "Unknown opcode ({})", self.code[offset])?;
+                Ok(offset + 1)
+            }
+            OpCode::Return => Self::simple_instruction(op, offset, out),
+            OpCode::Constant => self.constant_instruction(op, offset, false, out),
+            OpCode::ConstantLong => self.constant_instruction(op, offset, true, out),
+            OpCode::Nil => Self::simple_instruction(op, offset, out),
+            OpCode::True => Self::simple_instruction(op, offset, out),
+            OpCode::False => Self::simple_instruction(op, offset, out),
+            OpCode::Negate => Self::simple_instruction(op, offset, out),
+            OpCode::Not => Self::simple_instruction(op, offset, out),
+            OpCode::Add => Self::simple_instruction(op, offset, out),
+            OpCode::Subtract => Self::simple_instruction(op, offset, out),
+            OpCode::Multiply => Self::simple_instruction(op, offset, out),
+            OpCode::Divide => Self::simple_instruction(op, offset, out),
+            OpCode::FloorDivide => Self::simple_instruction(op, offset, out),
+            OpCode::Modulo => Self::simple_instruction(op, offset, out),
+            OpCode::Power => Self::simple_instruction(op, offset, out),
+            OpCode::BitAnd => Self::simple_instruction(op, offset, out),
+            OpCode::BitOr => Self::simple_instruction(op, offset, out),
+            OpCode::BitXor => Self::simple_instruction(op, offset, out),
+            OpCode::ShiftLeft => Self::simple_instruction(op, offset, out),
+            OpCode::ShiftRight => Self::simple_instruction(op, offset, out),
+            OpCode::BitNot => Self::simple_instruction(op, offset, out),
+            OpCode::Equal => Self::simple_instruction(op, offset, out),
+            OpCode::Less => Self::simple_instruction(op, offset, out),
+            OpCode::Greater => Self::simple_instruction(op, offset, out),
+            OpCode::NotEqual => Self::simple_instruction(op, offset, out),
+            OpCode::GreaterEqual => Self::simple_instruction(op, offset, out),
+            OpCode::LessEqual => Self::simple_instruction(op, offset, out),
+            OpCode::Pop => Self::simple_instruction(op, offset, out),
+            OpCode::DefineGlobal => self.constant_instruction(op, offset, true, out),
+            OpCode::GetGlobal => self.constant_instruction(op, offset, true, out),
+            OpCode::SetGlobal => self.constant_instruction(op, offset, true, out),
+            OpCode::Call => {
+                writeln!(out, "{} {}", op, self.code[offset + 1])?;
+                Ok(offset + 2)
             }
-            OpCode::Return => Self::simple_instruction(op, offset),
-            OpCode::Constant => self.constant_instruction(op, offset, false),
-            OpCode::ConstantLong => self.constant_instruction(op, offset, true),
-            OpCode::Nil => Self::simple_instruction(op, offset),
-            OpCode::True => Self::simple_instruction(op, offset),
-            OpCode::False => Self::simple_instruction(op, offset),
-            OpCode::Negate => Self::simple_instruction(op, offset),
-            OpCode::Not => Self::simple_instruction(op, offset),
-            OpCode::Add => Self::simple_instruction(op, offset),
-            OpCode::Subtract => Self::simple_instruction(op, offset),
-            OpCode::Multiply => Self::simple_instruction(op, offset),
-            OpCode::Divide => Self::simple_instruction(op, offset),
-            OpCode::Equal => Self::simple_instruction(op, offset),
-            OpCode::Less => Self::simple_instruction(op, offset),
-            OpCode::Greater => Self::simple_instruction(op, offset),
+            OpCode::Index => Self::simple_instruction(op, offset, out),
+            OpCode::ToString => Self::simple_instruction(op, offset, out),
         }
     }
 
-    fn simple_instruction(instr: OpCode, offset: usize) -> usize {
-        println!("{}", instr);
-        offset + 1
+    fn simple_instruction(instr: OpCode, offset: usize, out: &mut dyn Write) -> io::Result<usize> {
+        writeln!(out, "{}", instr)?;
+        Ok(offset + 1)
     }
 
-    fn constant_instruction(&self, instr: OpCode, offset: usize, is_long: bool) -> usize {
+    fn constant_instruction(
+        &self,
+        instr: OpCode,
+        offset: usize,
+        is_long: bool,
+        out: &mut dyn Write,
+    ) -> io::Result<usize> {
         if is_long {
             let constant_idx = (self.code[offset + 1] as u32) << 24
                 | (self.code[offset + 2] as u32) << 16
                 | (self.code[offset + 3] as u32) << 8
                 | (self.code[offset + 4] as u32);
-            println!(
+            writeln!(
+                out,
                 "{} {} '{}'",
                 instr, constant_idx, self.constants[constant_idx as usize]
-            );
-            offset + 5
+            )?;
+            Ok(offset + 5)
         } else {
             let constant_idx = self.code[offset + 1];
-            println!(
+            writeln!(
+                out,
                 "{} {} '{}'",
                 instr, constant_idx, self.constants[constant_idx as usize]
-            );
-            offset + 2
+            )?;
+            Ok(offset + 2)
         }
     }
 }
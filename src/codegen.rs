@@ -0,0 +1,150 @@
+use crate::ast::{BinaryOp, Expr, ExprKind, Stmt, StmtKind, UnaryOp};
+use crate::chunk::Chunk;
+use crate::opcode::OpCode;
+use crate::value::Value;
+
+#[cfg(feature = "register-vm")]
+pub mod registers;
+
+/// Generates the bytecode for `program`, the chunk returns the value of its last statement
+/// if that's an expression statement and `nil` otherwise
+pub fn generate(program: &[Stmt]) -> Chunk {
+    let mut generator = CodeGenerator {
+        chunk: Chunk::new(),
+    };
+
+    for (i, stmt) in program.iter().enumerate() {
+        generator.statement(stmt, i + 1 == program.len());
+    }
+
+    let line = program.last().map_or(1, |stmt| stmt.span.line);
+    generator.chunk.returns_expression = matches!(
+        program.last(),
+        Some(Stmt {
+            kind: StmtKind::Expression(_),
+            ..
+        })
+    );
+    if !generator.chunk.returns_expression {
+        generator.emit_byte(OpCode::Nil as u8, line);
+    }
+    generator.emit_byte(OpCode::Return as u8, line);
+
+    generator.chunk
+}
+
+struct CodeGenerator {
+    chunk: Chunk,
+}
+
+impl CodeGenerator {
+    fn statement(&mut self, stmt: &Stmt, is_last: bool) {
+        match &stmt.kind {
+            StmtKind::Expression(expr) => {
+                self.expression(expr);
+                if !is_last {
+                    self.emit_byte(OpCode::Pop as u8, stmt.span.line);
+                }
+            }
+            StmtKind::Var { name, initializer } => {
+                match initializer {
+                    Some(expr) => self.expression(expr),
+                    None => self.emit_byte(OpCode::Nil as u8, stmt.span.line),
+                }
+                self.emit_global(OpCode::DefineGlobal, name, stmt.span.line);
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(val) => self.emit_literal(val.clone(), expr.span.line),
+            ExprKind::Variable(name) => self.emit_global(OpCode::GetGlobal, name, expr.span.line),
+            ExprKind::Assign { name, value } => {
+                self.expression(value);
+                self.emit_global(OpCode::SetGlobal, name, expr.span.line);
+            }
+            ExprKind::Grouping(inner) => self.expression(inner),
+            ExprKind::Call { callee, args } => {
+                self.expression(callee);
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.emit_byte(OpCode::Call as u8, expr.span.line);
+                self.emit_byte(args.len() as u8, expr.span.line);
+            }
+            ExprKind::Index { target, index } => {
+                self.expression(target);
+                self.expression(index);
+                self.emit_byte(OpCode::Index as u8, expr.span.line);
+            }
+            ExprKind::Unary {
+                op,
+                op_span,
+                operand,
+            } => {
+                self.expression(operand);
+                let opcode = match op {
+                    UnaryOp::Negate => OpCode::Negate,
+                    UnaryOp::Not => OpCode::Not,
+                    UnaryOp::ToString => OpCode::ToString,
+                    UnaryOp::BitNot => OpCode::BitNot,
+                };
+                self.emit_byte(opcode as u8, op_span.line);
+            }
+            ExprKind::Binary {
+                op,
+                op_span,
+                lhs,
+                rhs,
+            } => {
+                self.expression(lhs);
+                self.expression(rhs);
+                let opcode = match op {
+                    BinaryOp::Add => OpCode::Add,
+                    BinaryOp::Subtract => OpCode::Subtract,
+                    BinaryOp::Multiply => OpCode::Multiply,
+                    BinaryOp::Divide => OpCode::Divide,
+                    BinaryOp::FloorDivide => OpCode::FloorDivide,
+                    BinaryOp::Modulo => OpCode::Modulo,
+                    BinaryOp::Power => OpCode::Power,
+                    BinaryOp::BitAnd => OpCode::BitAnd,
+                    BinaryOp::BitOr => OpCode::BitOr,
+                    BinaryOp::BitXor => OpCode::BitXor,
+                    BinaryOp::ShiftLeft => OpCode::ShiftLeft,
+                    BinaryOp::ShiftRight => OpCode::ShiftRight,
+                    BinaryOp::Equal => OpCode::Equal,
+                    BinaryOp::NotEqual => OpCode::NotEqual,
+                    BinaryOp::Greater => OpCode::Greater,
+                    BinaryOp::GreaterEqual => OpCode::GreaterEqual,
+                    BinaryOp::Less => OpCode::Less,
+                    BinaryOp::LessEqual => OpCode::LessEqual,
+                };
+                self.emit_byte(opcode as u8, op_span.line);
+            }
+        }
+    }
+
+    fn emit_byte(&mut self, byte: u8, line: usize) {
+        self.chunk.append(byte, line as u32);
+    }
+
+    fn emit_global(&mut self, op: OpCode, name: &str, line: usize) {
+        self.chunk.append_global(op, name, line as u32);
+    }
+
+    fn emit_literal(&mut self, val: Value, line: usize) {
+        if val.is_nil() {
+            self.emit_byte(OpCode::Nil as u8, line);
+        } else if val.is_bool() {
+            let opcode = if val.as_bool() {
+                OpCode::True
+            } else {
+                OpCode::False
+            };
+            self.emit_byte(opcode as u8, line);
+        } else {
+            self.chunk.append_constant(val, line as u32);
+        }
+    }
+}
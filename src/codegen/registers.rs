@@ -0,0 +1,185 @@
+use crate::ast::{BinaryOp, Expr, ExprKind, Stmt, StmtKind, UnaryOp};
+use crate::object::Object;
+use crate::register_chunk::{Instruction, Operand, RegisterChunk};
+use crate::value::Value;
+
+/// Generates register based code for `program`, like `codegen::generate` the value of
+/// its last expression statement is what the chunk returns
+pub fn generate(program: &[Stmt]) -> Result<RegisterChunk, &'static str> {
+    let mut generator = RegisterGenerator {
+        chunk: RegisterChunk::new(),
+        top: 0,
+    };
+
+    let mut result = None;
+    for stmt in program {
+        match &stmt.kind {
+            StmtKind::Expression(expr) => {
+                result = Some(generator.expression(expr)?);
+            }
+            StmtKind::Var { name, initializer } => {
+                let src = match initializer {
+                    Some(expr) => generator.expression(expr)?,
+                    None => Operand::Constant(generator.chunk.add_constant(Value::nil())),
+                };
+                let name = generator.name(name);
+                generator.chunk.append(
+                    Instruction::DefineGlobal { name, src },
+                    stmt.span.line as u32,
+                );
+                result = None;
+            }
+        }
+        // Nothing else reads the value of a statement
+        generator.top = 0;
+    }
+
+    let line = program.last().map_or(1, |stmt| stmt.span.line);
+    generator.chunk.returns_expression = result.is_some();
+    let src = match result {
+        Some(src) => src,
+        None => Operand::Constant(generator.chunk.add_constant(Value::nil())),
+    };
+    generator
+        .chunk
+        .append(Instruction::Return { src }, line as u32);
+
+    Ok(generator.chunk)
+}
+
+struct RegisterGenerator {
+    chunk: RegisterChunk,
+    /// The first free register, registers are allocated like a stack
+    top: usize,
+}
+
+impl RegisterGenerator {
+    /// Compiles `expr`, returning where its value can be found
+    fn expression(&mut self, expr: &Expr) -> Result<Operand, &'static str> {
+        match &expr.kind {
+            ExprKind::Literal(val) => Ok(Operand::Constant(self.chunk.add_constant(val.clone()))),
+            ExprKind::Variable(name) => {
+                let name = self.name(name);
+                let dst = self.allocate()?;
+                self.chunk
+                    .append(Instruction::GetGlobal { dst, name }, expr.span.line as u32);
+                Ok(Operand::Register(dst))
+            }
+            ExprKind::Assign { name, value } => {
+                let src = self.expression(value)?;
+                let name = self.name(name);
+                self.chunk
+                    .append(Instruction::SetGlobal { name, src }, expr.span.line as u32);
+                Ok(src)
+            }
+            ExprKind::Grouping(inner) => self.expression(inner),
+            ExprKind::Call { callee, args } => {
+                let base = self.top;
+                // The callee and its arguments have to be in consecutive registers
+                for expr in std::iter::once(&**callee).chain(args) {
+                    let slot = self.allocate()?;
+                    let src = self.expression(expr)?;
+                    if src != Operand::Register(slot) {
+                        self.chunk
+                            .append(Instruction::Move { dst: slot, src }, expr.span.line as u32);
+                    }
+                    self.top = slot as usize + 1;
+                }
+                // Arguments are read before the destination is written, so it can reuse their registers
+                self.top = base;
+                let dst = self.allocate()?;
+
+                let instr = Instruction::Call {
+                    dst,
+                    base: base as u8,
+                    argc: args.len() as u8,
+                };
+                self.chunk.append(instr, expr.span.line as u32);
+                Ok(Operand::Register(dst))
+            }
+            ExprKind::Index { target, index } => {
+                let base = self.top;
+                let lhs = self.expression(target)?;
+                let rhs = self.expression(index)?;
+                self.top = base;
+                let dst = self.allocate()?;
+
+                self.chunk
+                    .append(Instruction::Index { dst, lhs, rhs }, expr.span.line as u32);
+                Ok(Operand::Register(dst))
+            }
+            ExprKind::Unary {
+                op,
+                op_span,
+                operand,
+            } => {
+                let base = self.top;
+                let src = self.expression(operand)?;
+                self.top = base;
+                let dst = self.allocate()?;
+
+                let instr = match op {
+                    UnaryOp::Negate => Instruction::Negate { dst, src },
+                    UnaryOp::Not => Instruction::Not { dst, src },
+                    UnaryOp::ToString => Instruction::ToString { dst, src },
+                    UnaryOp::BitNot => Instruction::BitNot { dst, src },
+                };
+                self.chunk.append(instr, op_span.line as u32);
+                Ok(Operand::Register(dst))
+            }
+            ExprKind::Binary {
+                op,
+                op_span,
+                lhs,
+                rhs,
+            } => {
+                let base = self.top;
+                let lhs = self.expression(lhs)?;
+                let rhs = self.expression(rhs)?;
+                // Operands are read before the destination is written, so it can reuse their registers
+                self.top = base;
+                let dst = self.allocate()?;
+
+                let instr = match op {
+                    BinaryOp::Add => Instruction::Add { dst, lhs, rhs },
+                    BinaryOp::Subtract => Instruction::Subtract { dst, lhs, rhs },
+                    BinaryOp::Multiply => Instruction::Multiply { dst, lhs, rhs },
+                    BinaryOp::Divide => Instruction::Divide { dst, lhs, rhs },
+                    BinaryOp::FloorDivide => Instruction::FloorDivide { dst, lhs, rhs },
+                    BinaryOp::Modulo => Instruction::Modulo { dst, lhs, rhs },
+                    BinaryOp::Power => Instruction::Power { dst, lhs, rhs },
+                    BinaryOp::BitAnd => Instruction::BitAnd { dst, lhs, rhs },
+                    BinaryOp::BitOr => Instruction::BitOr { dst, lhs, rhs },
+                    BinaryOp::BitXor => Instruction::BitXor { dst, lhs, rhs },
+                    BinaryOp::ShiftLeft => Instruction::ShiftLeft { dst, lhs, rhs },
+                    BinaryOp::ShiftRight => Instruction::ShiftRight { dst, lhs, rhs },
+                    BinaryOp::Equal => Instruction::Equal { dst, lhs, rhs },
+                    BinaryOp::NotEqual => Instruction::NotEqual { dst, lhs, rhs },
+                    BinaryOp::Greater => Instruction::Greater { dst, lhs, rhs },
+                    BinaryOp::GreaterEqual => Instruction::GreaterEqual { dst, lhs, rhs },
+                    BinaryOp::Less => Instruction::Less { dst, lhs, rhs },
+                    BinaryOp::LessEqual => Instruction::LessEqual { dst, lhs, rhs },
+                };
+                self.chunk.append(instr, op_span.line as u32);
+                Ok(Operand::Register(dst))
+            }
+        }
+    }
+
+    /// Returns the constant holding the name of a global
+    fn name(&mut self, name: &str) -> u32 {
+        self.chunk
+            .add_constant(Value::object(Object::String(name.to_string())))
+    }
+
+    fn allocate(&mut self) -> Result<u8, &'static str> {
+        if self.top > u8::MAX as usize {
+            return Err("Expression needs too many registers");
+        }
+
+        let register = self.top as u8;
+        self.top += 1;
+        self.chunk.registers = self.chunk.registers.max(self.top);
+        Ok(register)
+    }
+}
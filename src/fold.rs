@@ -0,0 +1,249 @@
+use crate::arithmetic::{self, ArithOp};
+use crate::ast::{BinaryOp, Expr, ExprKind, Span, Stmt, StmtKind, UnaryOp};
+use crate::object::Object;
+use crate::value::Value;
+use std::cmp::Ordering;
+
+/// An operation on literals whose types mean it would always fail at runtime
+pub struct FoldError {
+    pub span: Span, // The span of the offending operator
+    pub message: &'static str,
+}
+
+/// Evaluates operators whose operands are all literals at compile time. Every type mismatch
+/// is reported, not just the first one in each statement. Failures that depend on the values,
+/// like an overflow or `1 ~/ 0`, are left unfolded to fail at runtime, if that's ever reached
+pub fn fold(program: Vec<Stmt>) -> Result<Vec<Stmt>, Vec<FoldError>> {
+    let mut errors = Vec::new();
+    let folded = program
+        .into_iter()
+        .map(|stmt| Stmt {
+            kind: fold_stmt(stmt.kind, &mut errors),
+            span: stmt.span,
+            doc: stmt.doc,
+        })
+        .collect();
+
+    if errors.is_empty() {
+        Ok(folded)
+    } else {
+        Err(errors)
+    }
+}
+
+fn fold_stmt(kind: StmtKind, errors: &mut Vec<FoldError>) -> StmtKind {
+    match kind {
+        StmtKind::Expression(expr) => StmtKind::Expression(fold_expr(expr, errors)),
+        StmtKind::Var { name, initializer } => StmtKind::Var {
+            name,
+            initializer: initializer.map(|expr| fold_expr(expr, errors)),
+        },
+    }
+}
+
+/// Folds what it can of `expr`, an operation that fails is left as it is
+fn fold_expr(expr: Expr, errors: &mut Vec<FoldError>) -> Expr {
+    let kind = match expr.kind {
+        ExprKind::Assign { name, value } => ExprKind::Assign {
+            name,
+            value: Box::new(fold_expr(*value, errors)),
+        },
+        ExprKind::Call { callee, args } => ExprKind::Call {
+            callee: Box::new(fold_expr(*callee, errors)),
+            args: args.into_iter().map(|arg| fold_expr(arg, errors)).collect(),
+        },
+        ExprKind::Index { target, index } => ExprKind::Index {
+            target: Box::new(fold_expr(*target, errors)),
+            index: Box::new(fold_expr(*index, errors)),
+        },
+        ExprKind::Grouping(inner) => {
+            let inner = fold_expr(*inner, errors);
+            match inner.kind {
+                ExprKind::Literal(_) => inner.kind,
+                _ => ExprKind::Grouping(Box::new(inner)),
+            }
+        }
+        ExprKind::Unary {
+            op,
+            op_span,
+            operand,
+        } => {
+            let operand = fold_expr(*operand, errors);
+            let folded = match &operand.kind {
+                ExprKind::Literal(val) => fold_unary(op, val)
+                    .map_err(|message| report(errors, op_span, message))
+                    .ok(),
+                _ => None,
+            };
+            match folded {
+                Some(val) => ExprKind::Literal(val),
+                None => ExprKind::Unary {
+                    op,
+                    op_span,
+                    operand: Box::new(operand),
+                },
+            }
+        }
+        ExprKind::Binary {
+            op,
+            op_span,
+            lhs,
+            rhs,
+        } => {
+            let lhs = fold_expr(*lhs, errors);
+            let rhs = fold_expr(*rhs, errors);
+            let folded = match (&lhs.kind, &rhs.kind) {
+                (ExprKind::Literal(a), ExprKind::Literal(b)) => fold_binary(op, a, b)
+                    .map_err(|message| report(errors, op_span, message))
+                    .ok(),
+                _ => None,
+            };
+            match folded {
+                Some(val) => ExprKind::Literal(val),
+                None => ExprKind::Binary {
+                    op,
+                    op_span,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+            }
+        }
+        kind => kind,
+    };
+
+    Expr::new(kind, expr.span)
+}
+
+/// Reports a failing operation if it's a type mismatch, others are left for the VM to raise
+fn report(errors: &mut Vec<FoldError>, span: Span, message: &'static str) {
+    let by_value = [
+        arithmetic::OVERFLOW,
+        arithmetic::DIVISION_BY_ZERO,
+        arithmetic::SHIFT_OUT_OF_RANGE,
+    ];
+    if !by_value.contains(&message) {
+        errors.push(FoldError { span, message });
+    }
+}
+
+/// Evaluates a unary operator at compile time, mirroring what the VM would do at runtime
+fn fold_unary(op: UnaryOp, val: &Value) -> Result<Value, &'static str> {
+    match op {
+        UnaryOp::Negate => arithmetic::negate(val),
+        UnaryOp::BitNot => arithmetic::bit_not(val),
+        UnaryOp::Not => Ok(Value::boolean(val.is_falsey())),
+        UnaryOp::ToString if val.is_string() => Ok(val.clone()),
+        UnaryOp::ToString => Ok(Value::object(Object::String(val.to_string()))),
+    }
+}
+
+/// Evaluates a binary operator at compile time, mirroring what the VM would do at runtime.
+/// Notably `>=` and `<=` are folded as the negation of `<` and `>` as that's how they are executed
+fn fold_binary(op: BinaryOp, a: &Value, b: &Value) -> Result<Value, &'static str> {
+    let arith = |op| arithmetic::arithmetic(op, a, b);
+    let compare = |test: fn(Option<Ordering>) -> bool| {
+        arithmetic::compare(a, b).map(|ord| Value::boolean(test(ord)))
+    };
+    match op {
+        BinaryOp::Equal => Ok(Value::boolean(arithmetic::equal(a, b))),
+        BinaryOp::NotEqual => Ok(Value::boolean(!arithmetic::equal(a, b))),
+        BinaryOp::Add if a.is_string() && b.is_string() => {
+            let new = format!("{}{}", a.as_string(), b.as_string());
+            Ok(Value::object(Object::String(new)))
+        }
+        BinaryOp::Add => arith(ArithOp::Add),
+        BinaryOp::Subtract => arith(ArithOp::Subtract),
+        BinaryOp::Multiply => arith(ArithOp::Multiply),
+        BinaryOp::Divide => arith(ArithOp::Divide),
+        BinaryOp::FloorDivide => arith(ArithOp::FloorDivide),
+        BinaryOp::Modulo => arith(ArithOp::Modulo),
+        BinaryOp::Power => arith(ArithOp::Power),
+        BinaryOp::BitAnd => arith(ArithOp::BitAnd),
+        BinaryOp::BitOr => arith(ArithOp::BitOr),
+        BinaryOp::BitXor => arith(ArithOp::BitXor),
+        BinaryOp::ShiftLeft => arith(ArithOp::ShiftLeft),
+        BinaryOp::ShiftRight => arith(ArithOp::ShiftRight),
+        BinaryOp::Greater => compare(|ord| ord == Some(Ordering::Greater)),
+        BinaryOp::GreaterEqual => compare(|ord| ord != Some(Ordering::Less)),
+        BinaryOp::Less => compare(|ord| ord == Some(Ordering::Less)),
+        BinaryOp::LessEqual => compare(|ord| ord != Some(Ordering::Greater)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::compile;
+    use crate::vm::VM;
+    use std::io;
+
+    /// The value of running `source`, or the error it fails with
+    fn run(source: &str) -> Result<String, String> {
+        let chunk = compile(source).map_err(|errors| errors[0].to_string())?;
+        let mut vm = VM::new();
+        vm.set_stderr(Box::new(io::sink()));
+        vm.execute(chunk)
+            .map(|val| val.to_string())
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    #[test]
+    fn folded_and_unfolded_operations_agree() {
+        for (a, op, b) in [
+            ("7", "+", "2"),
+            ("7", "-", "2.5"),
+            ("7", "*", "3"),
+            ("7", "/", "2"),
+            ("1", "/", "0.0"),
+            ("-1", "/", "0"),
+            ("0.0", "/", "0"),
+            ("7", "~/", "-2"),
+            ("7.5", "~/", "2"),
+            ("-7", "%", "3"),
+            ("2", "**", "10"),
+            ("2", "**", "-1"),
+            ("6", "&", "3"),
+            ("6", "^", "3"),
+            ("1", "<<", "4"),
+            ("-8", ">>", "1"),
+            ("1", "<", "2.5"),
+            ("2", ">=", "2.0"),
+            ("9007199254740993", ">", "9007199254740992.0"),
+            ("\"ab\"", "+", "\"cd\""),
+            ("\"a\"", "==", "\"a\""),
+            ("1", "==", "1.0"),
+            ("nil", "!=", "false"),
+            ("9223372036854775807", "+", "1"),
+            ("1", "~/", "0"),
+            ("1", "%", "0"),
+            ("2", "**", "64"),
+            ("1", "<<", "64"),
+            ("1", ">>", "-1"),
+        ] {
+            let folded = format!("{} {} {}", a, op, b);
+            let unfolded = format!("var a = {}; var b = {}; a {} b", a, b, op);
+            assert_eq!(run(&folded), run(&unfolded), "{}", folded);
+        }
+
+        for (op, operand) in [
+            ("-", "5"),
+            ("-", "2.5"),
+            ("!", "nil"),
+            ("!", "0"),
+            ("~", "5"),
+        ] {
+            let folded = format!("{}{}", op, operand);
+            let unfolded = format!("var a = {}; {}a", operand, op);
+            assert_eq!(run(&folded), run(&unfolded), "{}", folded);
+        }
+    }
+
+    #[test]
+    fn only_type_mismatches_are_compile_errors() {
+        assert_eq!(
+            run("\"a\" - 1").unwrap_err(),
+            "[line 1] Error at - Operands must be numbers"
+        );
+        assert_eq!(run("1 ~/ 0").unwrap_err(), "Runtime");
+        assert!(compile("exit(0); 1 ~/ 0; 1 << 64; 9223372036854775807 + 1;").is_ok());
+    }
+}
@@ -0,0 +1,49 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Lets other threads stop the script a VM is running, which then fails with
+/// `VMError::Interrupted`. Obtained from `VM::interrupt_handle`.
+#[derive(Clone, Default)]
+pub struct InterruptHandle {
+    runs: Arc<Runs>,
+}
+
+#[derive(Default)]
+struct Runs {
+    counter: AtomicU64, // Counts the runs started and finished, so it's odd while one is going
+    requested: AtomicU64, // The value of `counter` when an interruption was last requested
+}
+
+impl InterruptHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the VM to stop, it will do so the next time it checks for interruptions.
+    /// Requests only apply to the run that's going on, compiling included, if there's
+    /// none they're ignored
+    pub fn interrupt(&self) {
+        let run = self.runs.counter.load(Ordering::Relaxed);
+        if run % 2 == 1 {
+            self.runs.requested.store(run, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        self.runs.counter.load(Ordering::Relaxed) % 2 == 1
+    }
+
+    pub(crate) fn start_run(&self) {
+        self.runs.counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn finish_run(&self) {
+        self.runs.counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether the current run was asked to stop
+    pub(crate) fn is_requested(&self) -> bool {
+        let run = self.runs.counter.load(Ordering::Relaxed);
+        run % 2 == 1 && self.runs.requested.load(Ordering::Relaxed) == run
+    }
+}
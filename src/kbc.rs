@@ -0,0 +1,278 @@
+//! Reading and writing compiled chunks as `.kbc` files. All integers are little endian:
+//!
+//! ```text
+//! magic       "KBC\0"
+//! version     u8
+//! flags       u8, bit 0 is `Chunk::returns_expression`
+//! constants   u32 count, then per constant a tag byte and its payload:
+//!             0 nil, 1 false, 2 true, 3 number (f64 bits as u64), 4 string (u32 length, UTF-8 bytes),
+//!             5 int (i64)
+//! code        u32 length, then the bytes
+//! lines       one u32 per byte of code
+//! ```
+
+use crate::chunk::Chunk;
+use crate::object::Object;
+use crate::opcode::OpCode;
+use crate::value::Value;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"KBC\0";
+const VERSION: u8 = 1;
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_INT: u8 = 5;
+
+/// Whether `bytes` look like the contents of a `.kbc` file rather than source code
+pub fn is_kbc(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn write(chunk: &Chunk, out: &mut dyn Write) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION, chunk.returns_expression as u8])?;
+
+    let constants = chunk.constants();
+    write_u32(out, constants.len() as u32)?;
+    for idx in 0..constants.len() {
+        let val = &constants[idx];
+        if val.is_nil() {
+            out.write_all(&[TAG_NIL])?;
+        } else if val.is_bool() {
+            out.write_all(&[if val.as_bool() { TAG_TRUE } else { TAG_FALSE }])?;
+        } else if val.is_number() {
+            out.write_all(&[TAG_NUMBER])?;
+            out.write_all(&val.to_number().to_bits().to_le_bytes())?;
+        } else if val.is_int() {
+            out.write_all(&[TAG_INT])?;
+            out.write_all(&val.as_int().to_le_bytes())?;
+        } else {
+            let stri = val.as_string();
+            out.write_all(&[TAG_STRING])?;
+            write_u32(out, stri.len() as u32)?;
+            out.write_all(stri.as_bytes())?;
+        }
+    }
+
+    write_u32(out, chunk.len() as u32)?;
+    out.write_all(chunk.code())?;
+    for &line in &chunk.lines {
+        write_u32(out, line)?;
+    }
+    Ok(())
+}
+
+/// Reads a chunk written by `write`, checking that the VM can run it safely
+pub fn read(input: &mut dyn Read) -> io::Result<Chunk> {
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a kbc file"));
+    }
+    let [version, flags] = read_bytes::<2>(input)?;
+    if version != VERSION {
+        return Err(invalid("unsupported kbc version"));
+    }
+
+    let mut chunk = Chunk::new();
+    chunk.returns_expression = flags & 1 != 0;
+
+    let count = read_u32(input)?;
+    for expected in 0..count {
+        let val = match read_bytes::<1>(input)?[0] {
+            TAG_NIL => Value::nil(),
+            TAG_FALSE => Value::boolean(false),
+            TAG_TRUE => Value::boolean(true),
+            TAG_NUMBER => Value::number(f64::from_bits(u64::from_le_bytes(read_bytes(input)?))),
+            TAG_INT => Value::int(i64::from_le_bytes(read_bytes(input)?)),
+            TAG_STRING => {
+                let len = read_u32(input)?;
+                let bytes = read_vec(input, len)?;
+                let stri = String::from_utf8(bytes).map_err(|_| invalid("invalid string"))?;
+                Value::object(Object::String(stri))
+            }
+            _ => return Err(invalid("unknown constant type")),
+        };
+        // Identical constants would be merged, shifting the indices of the ones after them
+        if chunk.add_constant(val) != expected {
+            return Err(invalid("duplicate constant"));
+        }
+    }
+
+    let len = read_u32(input)?;
+    let code = read_vec(input, len)?;
+    let mut lines = Vec::with_capacity(code.len());
+    for _ in 0..code.len() {
+        lines.push(read_u32(input)?);
+    }
+    chunk.set_code(code, lines);
+
+    validate(&chunk)?;
+    Ok(chunk)
+}
+
+/// Checks that every instruction is known and complete, that constants exist, that
+/// no instruction takes more values than the stack holds and that the code ends with a `Return`
+fn validate(chunk: &Chunk) -> io::Result<()> {
+    let code = chunk.code();
+    let constants = chunk.constants();
+    let mut offset = 0;
+    let mut last = OpCode::Unknown;
+    // There are no jumps, so the depth at each instruction is known
+    let mut depth: usize = 0;
+
+    while offset < code.len() {
+        last = OpCode::from(code[offset]);
+        if last == OpCode::Unknown {
+            return Err(invalid("unknown opcode"));
+        }
+        let end = offset + last.size();
+        if end > code.len() {
+            return Err(invalid("truncated instruction"));
+        }
+
+        let operand = match last {
+            OpCode::Constant => Some(code[offset + 1] as usize),
+            OpCode::ConstantLong | OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(&code[offset + 1..end]);
+                Some(u32::from_be_bytes(bytes) as usize)
+            }
+            _ => None,
+        };
+        if let Some(idx) = operand {
+            if idx >= constants.len() {
+                return Err(invalid("missing constant"));
+            }
+            let is_global = matches!(
+                last,
+                OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal
+            );
+            if is_global && !constants[idx].is_string() {
+                return Err(invalid("global name isn't a string"));
+            }
+        }
+
+        let (pops, pushes) = stack_effect(last, &code[offset..end]);
+        depth = depth
+            .checked_sub(pops)
+            .ok_or_else(|| invalid("stack underflow"))?
+            + pushes;
+
+        offset = end;
+    }
+
+    if last != OpCode::Return {
+        return Err(invalid("code doesn't end with Return"));
+    }
+    Ok(())
+}
+
+/// How many values `instruction` takes off the stack and how many it leaves there
+fn stack_effect(op: OpCode, instruction: &[u8]) -> (usize, usize) {
+    match op {
+        OpCode::Constant
+        | OpCode::ConstantLong
+        | OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::GetGlobal => (0, 1),
+        OpCode::Negate | OpCode::Not | OpCode::ToString | OpCode::BitNot | OpCode::SetGlobal => {
+            (1, 1)
+        }
+        OpCode::Return | OpCode::Pop | OpCode::DefineGlobal => (1, 0),
+        OpCode::Call => (instruction[1] as usize + 1, 1),
+        _ => (2, 1),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_u32(out: &mut dyn Write, val: u32) -> io::Result<()> {
+    out.write_all(&val.to_le_bytes())
+}
+
+fn read_u32(input: &mut dyn Read) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(input)?))
+}
+
+/// Reads `len` bytes, the buffer only grows as they arrive so a corrupt length can't
+/// allocate more memory than the file holds
+fn read_vec(input: &mut dyn Read, len: u32) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    input.take(len.into()).read_to_end(&mut bytes)?;
+    if bytes.len() != len as usize {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn read_bytes<const N: usize>(input: &mut dyn Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(code: &[u8], constants: &[Value]) -> Vec<u8> {
+        let mut chunk = Chunk::new();
+        for val in constants {
+            chunk.add_constant(val.clone());
+        }
+        chunk.set_code(code.to_vec(), vec![1; code.len()]);
+        let mut bytes = Vec::new();
+        write(&chunk, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn error(bytes: &[u8]) -> io::Error {
+        match read(&mut &bytes[..]) {
+            Ok(_) => panic!("the chunk was accepted"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn compiled_code_round_trips() {
+        let source = "var x = \"a${1.5}\";\nx = -len(args) * 2;\nx";
+        let chunk = crate::compiler::compile(source).unwrap();
+        let mut bytes = Vec::new();
+        write(&chunk, &mut bytes).unwrap();
+        let read_back = read(&mut &bytes[..]).unwrap();
+        assert_eq!(read_back.code(), chunk.code());
+        assert_eq!(read_back.lines, chunk.lines);
+    }
+
+    #[test]
+    fn rejects_code_that_takes_more_values_than_the_stack_holds() {
+        let (pop, ret, add) = (OpCode::Pop as u8, OpCode::Return as u8, OpCode::Add as u8);
+        let (nil, call, index) = (OpCode::Nil as u8, OpCode::Call as u8, OpCode::Index as u8);
+        for code in [
+            &[pop, ret][..],
+            &[ret],
+            &[nil, add, ret],
+            &[nil, call, 1, ret],
+            &[nil, index, ret],
+        ] {
+            assert_eq!(error(&encode(code, &[])).to_string(), "stack underflow");
+        }
+        assert!(read(&mut &encode(&[nil, nil, call, 1, ret], &[])[..]).is_ok());
+    }
+
+    #[test]
+    fn lengths_beyond_the_end_of_the_file_are_errors() {
+        let mut bytes = encode(&[OpCode::Nil as u8, OpCode::Return as u8], &[]);
+        let code_len = MAGIC.len() + 2 + 4;
+        bytes[code_len..code_len + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(error(&bytes).kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
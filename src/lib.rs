@@ -0,0 +1,23 @@
+pub mod arithmetic;
+pub mod ast;
+pub mod chunk;
+pub mod codegen;
+pub mod compiler;
+pub mod fold;
+pub mod interrupt;
+pub mod kbc;
+pub mod natives;
+pub mod object;
+pub mod opcode;
+pub mod parser;
+pub mod peephole;
+#[cfg(feature = "register-vm")]
+pub mod register_chunk;
+pub mod scanner;
+pub mod trace;
+#[cfg(not(feature = "nan-boxing"))]
+pub mod value;
+#[cfg(feature = "nan-boxing")]
+#[path = "nan_value.rs"]
+pub mod value;
+pub mod vm;
@@ -1,69 +1,489 @@
-mod chunk;
-mod compiler;
-mod object;
-mod opcode;
-mod scanner;
-mod value;
-mod vm;
-
+use kurisu::chunk::Chunk;
+use kurisu::compiler::{self, CompileError};
+use kurisu::kbc;
+use kurisu::object::Object;
+use kurisu::scanner::{Scanner, TokenType};
+use kurisu::trace::{Trace, TRACE_ENV_VAR};
+use kurisu::value::Value;
+use kurisu::vm::VMError;
+use kurisu::vm::VM;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
 use std::env;
 use std::fs;
-use std::io::{self, BufRead, Write};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process;
-use vm::VMError;
-use vm::VM;
+use std::time::Instant;
+
+/// Where the REPL's history is kept, relative to the home directory
+const HISTORY_FILE: &str = ".kurisu_history";
+
+/// How runtime errors refer to code typed into the REPL
+const REPL_SCRIPT_NAME: &str = "repl";
+
+const USAGE: &str = "\
+Usage: kurisu [--trace=code,stack,instructions] [command] [<path> | - | -e <code>] [args...]
+
+Commands:
+    run         run source code or a compiled .kbc file, the default
+    check       compile without running, reporting every error
+    disasm      show the bytecode the code compiles to
+    tokens      show the tokens the code is made of
+    compile     compile to a .kbc file, named with -o <path>
+
+Without a command or code the REPL is started, a path of - reads the code from stdin.
+Anything after the code is passed to the script in the `args` list.
+Integer division is written `~/`, as `//` starts a comment.
+";
+
+// Exit codes, as defined by sysexits.h
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_SOFTWARE: i32 = 70;
+const EX_CANTCREAT: i32 = 73;
+
+const COMMANDS: [&str; 5] = ["run", "check", "disasm", "tokens", "compile"];
+
+const REPL_HELP: &str = "\
+:dis <code>     show the bytecode <code> compiles to
+:tokens <code>  show the tokens <code> is made of
+:stack          toggle tracing the stack, needs the trace feature
+:load <path>    run a file in the current session
+:reset          forget all variables
+:time <code>    run <code> and show how long it took
+:help           show this message
+";
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() == 1 {
-        repl();
-    } else if args.len() == 2 {
-        run_file(&args[1].as_ref());
-    } else {
-        println!("Usage: kurisu [path]");
-        process::exit(1);
+    let mut trace = Trace::default();
+    if let Ok(spec) = env::var(TRACE_ENV_VAR) {
+        enable_trace(&mut trace, &spec);
+    }
+
+    let options = parse_args(env::args().skip(1).collect()).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(EX_USAGE);
+    });
+    for spec in &options.trace {
+        enable_trace(&mut trace, spec);
     }
-}
 
-fn repl() {
     let mut vm = VM::new();
-    let stdin = io::stdin();
-    loop {
-        print!("> ");
-        io::stdout().flush().ok().expect("Could not flush stdout");
-        let mut line = String::new();
-        stdin
-            .lock()
-            .read_line(&mut line)
-            .expect("Could not read a line from stdin");
-        match vm.interpret(line.as_ref()) {
-            Ok(()) => (),
-            Err(e) => {
-                println!(
-                    "Error in line \n\t{}\n{}",
-                    line,
-                    match e {
-                        VMError::Runtime => "A runtime error occured",
-                        VMError::Compile => "An error related to compiling your code occured",
-                    }
-                );
+    vm.set_trace(trace);
+    let script_args = options
+        .script_args
+        .into_iter()
+        .map(|arg| Value::object(Object::String(arg)))
+        .collect();
+    vm.define_global("args", Value::object(Object::List(script_args)));
+    let input = match options.input {
+        Some(input) => input,
+        None if options.command.is_none() => return repl(vm),
+        None => {
+            eprintln!("No code was given\n\n{}", USAGE);
+            process::exit(EX_USAGE);
+        }
+    };
+    if options.output.is_some() && options.command.as_deref() != Some("compile") {
+        eprintln!("-o can only be used with compile\n\n{}", USAGE);
+        process::exit(EX_USAGE);
+    }
+
+    let code = read_code(&input);
+    let name = input.name();
+    vm.set_script_name(&name);
+    match options.command.as_deref().unwrap_or("run") {
+        "run" => run(vm, &name, &code),
+        "check" => check(&name, &source_text(code)),
+        "disasm" => disassemble_chunk(&load_chunk(&name, code)),
+        "tokens" => print_tokens(&source_text(code)),
+        "compile" => {
+            let output = match (options.output, &input) {
+                (Some(output), _) => PathBuf::from(output),
+                (None, Input::File(path)) => Path::new(path).with_extension("kbc"),
+                (None, _) => {
+                    eprintln!("compile needs -o <path> when the code isn't in a file");
+                    process::exit(EX_USAGE);
+                }
+            };
+            compile_to_file(&name, &source_text(code), &output);
+        }
+        _ => unreachable!("commands are checked by parse_args"),
+    }
+}
+
+/// Where the code a command works on comes from
+enum Input {
+    File(String),
+    Stdin,
+    Inline(String),
+}
+
+#[derive(Default)]
+struct Options {
+    command: Option<String>,
+    input: Option<Input>,
+    output: Option<String>,
+    trace: Vec<String>,
+    script_args: Vec<String>, // Everything after the code, which the script gets as `args`
+}
+
+impl Input {
+    /// How the code is referred to in error messages
+    fn name(&self) -> String {
+        match self {
+            Self::File(path) => path.clone(),
+            Self::Stdin => "<stdin>".to_string(),
+            Self::Inline(_) => "<-e>".to_string(),
+        }
+    }
+}
+
+impl Options {
+    fn set_input(&mut self, input: Input) -> Result<(), String> {
+        if self.input.is_some() {
+            return Err("Only one piece of code can be given".to_string());
+        }
+        self.input = Some(input);
+        Ok(())
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // compile doesn't run the code, so its options may come after it
+        if options.input.is_some() && options.command.as_deref() != Some("compile") {
+            options.script_args.push(arg);
+            continue;
+        }
+
+        if let Some(spec) = arg.strip_prefix("--trace=") {
+            options.trace.push(spec.to_string());
+            continue;
+        }
+        match arg.as_str() {
+            "-e" => {
+                let code = args.next().ok_or("-e needs the code to run")?;
+                options.set_input(Input::Inline(code))?;
+            }
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            "-o" => options.output = Some(args.next().ok_or("-o needs a path")?),
+            "-" => options.set_input(Input::Stdin)?,
+            _ if options.command.is_none()
+                && options.input.is_none()
+                && COMMANDS.contains(&arg.as_str()) =>
+            {
+                options.command = Some(arg)
             }
+            _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+            _ => options.set_input(Input::File(arg))?,
         }
     }
+    Ok(options)
+}
+
+/// Reads the code, without the `#!` line scripts in files or piped to stdin may start with
+fn read_code(input: &Input) -> Vec<u8> {
+    let result = match input {
+        Input::File(path) => fs::read(path),
+        Input::Stdin => {
+            let mut code = Vec::new();
+            io::stdin().read_to_end(&mut code).map(|_| code)
+        }
+        Input::Inline(code) => return code.clone().into_bytes(),
+    };
+    let mut code = result.unwrap_or_else(|e| {
+        match input {
+            Input::File(path) => eprintln!("Could not open file {}: {}", path, e),
+            _ => eprintln!("Could not read stdin: {}", e),
+        }
+        process::exit(EX_NOINPUT);
+    });
+    code.drain(..shebang_len(&code));
+    code
+}
+
+/// The length of the `#!` line `code` starts with, which lets scripts be run directly.
+/// Its newline isn't included, so removing it keeps line numbers right
+fn shebang_len(code: &[u8]) -> usize {
+    if !code.starts_with(b"#!") {
+        return 0;
+    }
+    code.iter().position(|&b| b == b'\n').unwrap_or(code.len())
 }
 
-fn run_file(file: &str) {
-    fn interpret(file: &str) -> Result<(), VMError> {
-        let mut vm = VM::new();
-        let contents =
-            fs::read_to_string(file).expect(format!("Could not open file {}\n", file).as_ref());
-        vm.interpret(contents.as_ref())
+fn source_text(code: Vec<u8>) -> String {
+    if kbc::is_kbc(&code) {
+        eprintln!("Expected source code, not a compiled .kbc file");
+        process::exit(EX_DATAERR);
     }
-    match interpret(file) {
+    String::from_utf8(code).unwrap_or_else(|_| {
+        eprintln!("The source code isn't valid UTF-8");
+        process::exit(EX_DATAERR);
+    })
+}
+
+/// Compiles `code`, or loads it if it's already compiled
+fn load_chunk(name: &str, code: Vec<u8>) -> Chunk {
+    if kbc::is_kbc(&code) {
+        return kbc::read(&mut code.as_slice()).unwrap_or_else(|e| {
+            eprintln!("{}: could not load the compiled code: {}", name, e);
+            process::exit(EX_DATAERR);
+        });
+    }
+
+    compiler::compile(&source_text(code)).unwrap_or_else(|errors| fail_compile(name, &errors))
+}
+
+fn run(mut vm: VM, name: &str, code: &[u8]) {
+    let result = if kbc::is_kbc(code) {
+        vm.interpret_chunk(load_chunk(name, code.to_vec()))
+    } else {
+        vm.interpret(&source_text(code.to_vec()))
+    };
+    // The VM has already reported the details, including the line
+    match result {
         Ok(()) => (),
+        Err(VMError::Exit(code)) => process::exit(code),
+        Err(e @ VMError::Compile) => {
+            eprintln!("{}: {}", name, e);
+            process::exit(EX_DATAERR);
+        }
         Err(e) => {
-            println!("Error running your file {}", e);
-            process::exit(3);
+            eprintln!("{}: {}", name, e);
+            process::exit(EX_SOFTWARE);
+        }
+    }
+}
+
+fn check(name: &str, source: &str) {
+    if let Err(errors) = compiler::compile(source) {
+        fail_compile(name, &errors);
+    }
+}
+
+fn fail_compile(name: &str, errors: &[CompileError]) -> ! {
+    for e in errors {
+        eprintln!("{}", e);
+    }
+    eprintln!("{}: {}", name, VMError::Compile);
+    process::exit(EX_DATAERR);
+}
+
+fn disassemble_chunk(chunk: &Chunk) {
+    if let Err(e) = chunk.disassemble("code", &mut io::stdout()) {
+        eprintln!("Could not print the code: {}", e);
+    }
+}
+
+fn compile_to_file(name: &str, source: &str, output: &Path) {
+    let chunk = load_chunk(name, source.as_bytes().to_vec());
+    let written = fs::File::create(output).and_then(|file| {
+        let mut out = io::BufWriter::new(file);
+        kbc::write(&chunk, &mut out)?;
+        out.flush()
+    });
+    if let Err(e) = written {
+        eprintln!("Could not write {}: {}", output.display(), e);
+        process::exit(EX_CANTCREAT);
+    }
+}
+
+fn enable_trace(trace: &mut Trace, spec: &str) {
+    if let Err(e) = trace.enable(spec) {
+        eprintln!("{}", e);
+        process::exit(EX_USAGE);
+    }
+    if cfg!(not(feature = "trace")) && (trace.stack || trace.instructions) {
+        eprintln!(
+            "Tracing the stack or instructions needs kurisu to be built with the trace feature"
+        );
+    }
+}
+
+fn repl(mut vm: VM) {
+    vm.set_script_name(REPL_SCRIPT_NAME);
+    let mut editor = DefaultEditor::new().unwrap_or_else(|e| {
+        eprintln!("Could not start the line editor: {}", e);
+        process::exit(EX_SOFTWARE);
+    });
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+    if let Some(path) = &history {
+        // There's no history the first time the REPL is used
+        let _ = editor.load_history(path);
+    }
+
+    let mut exit_code = None;
+    while let Some(line) = read_input(&mut editor) {
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.trim_end());
+        }
+        let exit = if line.starts_with(':') {
+            meta_command(&mut vm, &line)
+        } else {
+            vm.interpret(line.as_ref())
+                .err()
+                .and_then(|e| report_repl_error(&line, e))
+        };
+        if exit.is_some() {
+            exit_code = exit;
+            break;
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Could not save the history to {}: {}", path.display(), e);
+        }
+    }
+    if let Some(code) = exit_code {
+        process::exit(code);
+    }
+}
+
+/// Returns the exit code if the script asked to stop, otherwise reports the error
+fn report_repl_error(source: &str, e: VMError) -> Option<i32> {
+    if let VMError::Exit(code) = e {
+        return Some(code);
+    }
+    eprintln!("Error in line \n\t{}\n{}", source, e);
+    None
+}
+
+/// Runs a REPL command, i.e. a line starting with ':'. Returns the exit code if
+/// code it ran asked to stop
+fn meta_command(vm: &mut VM, line: &str) -> Option<i32> {
+    let line = line.trim();
+    let (command, arg) = match line.split_once(char::is_whitespace) {
+        Some((command, arg)) => (command, arg.trim()),
+        None => (line, ""),
+    };
+
+    match command {
+        ":dis" => disassemble(arg),
+        ":tokens" => print_tokens(arg),
+        ":stack" => {
+            let trace = vm.trace_mut();
+            trace.stack = !trace.stack;
+            println!(
+                "Stack tracing is {}",
+                if trace.stack { "on" } else { "off" }
+            );
+            if cfg!(not(feature = "trace")) {
+                eprintln!("Tracing the stack needs kurisu to be built with the trace feature");
+            }
+        }
+        ":load" => match fs::read_to_string(arg) {
+            Ok(source) => {
+                vm.set_script_name(arg);
+                let result = vm.interpret(&source[shebang_len(source.as_bytes())..]);
+                vm.set_script_name(REPL_SCRIPT_NAME);
+                if let Err(e) = result {
+                    return report_repl_error(&source, e);
+                }
+            }
+            Err(e) => eprintln!("Could not open file {}: {}", arg, e),
+        },
+        ":reset" => {
+            vm.reset();
+            println!("All variables were forgotten");
         }
+        ":time" => {
+            let start = Instant::now();
+            let result = vm.interpret(arg);
+            println!("Took {:?}", start.elapsed());
+            if let Err(e) = result {
+                return report_repl_error(arg, e);
+            }
+        }
+        ":help" => print!("{}", REPL_HELP),
+        _ => eprintln!("Unknown command {}, see :help", command),
+    }
+    None
+}
+
+/// Prints the code the VM would run for `source`
+fn disassemble(source: &str) {
+    #[cfg(not(feature = "register-vm"))]
+    let compiled = compiler::compile(source);
+    #[cfg(feature = "register-vm")]
+    let compiled = compiler::compile_registers(source);
+
+    match compiled {
+        Ok(chunk) => {
+            if let Err(e) = chunk.disassemble("code", &mut io::stdout()) {
+                eprintln!("Could not print the code: {}", e);
+            }
+        }
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{}", e);
+            }
+        }
+    }
+}
+
+fn print_tokens(source: &str) {
+    let mut scanner = Scanner::new(source);
+    loop {
+        let token = scanner.scan_token();
+        println!("l:{:04} {}", token.line, token);
+        if token.type_ == TokenType::Eof {
+            break;
+        }
+    }
+}
+
+/// Reads lines until they form a complete piece of source code, `None` once input ends
+fn read_input(editor: &mut DefaultEditor) -> Option<String> {
+    let mut source = String::new();
+    let mut prompt = "> ";
+    loop {
+        match editor.readline(prompt) {
+            Ok(line) => {
+                source.push_str(&line);
+                source.push('\n');
+                if !Scanner::is_incomplete(&source) {
+                    return Some(source);
+                }
+                prompt = "... ";
+            }
+            // Ctrl-C throws away what has been typed so far
+            Err(ReadlineError::Interrupted) => {
+                source.clear();
+                prompt = "> ";
+            }
+            Err(ReadlineError::Eof) => return None,
+            Err(e) => {
+                eprintln!("Could not read input: {}", e);
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shebang_lines_are_removed_without_their_newline() {
+        let source = "#!/usr/bin/env kurisu\n\nvar x = 1;";
+        let code = &source[shebang_len(source.as_bytes())..];
+        assert_eq!(code, "\n\nvar x = 1;");
+
+        let mut scanner = Scanner::new(code);
+        let token = scanner.scan_token();
+        assert_eq!((token.type_, token.line), (TokenType::Var, 3));
+
+        assert_eq!(shebang_len(b"#!kurisu"), 8);
+        assert_eq!(shebang_len(b"1\n#!kurisu"), 0);
     }
 }
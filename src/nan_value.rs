@@ -0,0 +1,262 @@
+//! A NaN-boxed `Value`, packing every value into a single `u64`.
+//!
+//! Numbers are stored as plain `f64`s. Everything else lives inside the space of quiet NaNs:
+//! nil and booleans are small tags, while objects set the sign bit and store their pointer in
+//! the low 48 bits. Ints set the `INT` bit, those that fit in 48 bits are stored in place and
+//! the others are boxed like objects are. NaNs produced by arithmetic are canonicalised so they can't be mistaken
+//! for any of the other kinds of values.
+
+use std::fmt;
+
+use crate::arithmetic;
+use crate::object::Object;
+
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+const POINTER_MASK: u64 = 0x0000_ffff_ffff_ffff;
+const INT: u64 = 0x0002_0000_0000_0000;
+
+const TAG_NIL: u64 = 1;
+const TAG_FALSE: u64 = 2;
+const TAG_TRUE: u64 = 3;
+
+const NIL: u64 = QNAN | TAG_NIL;
+const FALSE: u64 = QNAN | TAG_FALSE;
+const TRUE: u64 = QNAN | TAG_TRUE;
+
+pub struct Value(u64);
+
+impl Value {
+    pub fn nil() -> Self {
+        Value(NIL)
+    }
+
+    pub fn boolean(b: bool) -> Self {
+        Value(if b { TRUE } else { FALSE })
+    }
+
+    pub fn number(n: f64) -> Self {
+        if n.is_nan() {
+            Value(f64::NAN.to_bits())
+        } else {
+            Value(n.to_bits())
+        }
+    }
+
+    pub fn int(i: i64) -> Self {
+        // Whether `i` survives being truncated to 48 bits and sign extended back
+        if (i << 16) >> 16 == i {
+            Value(QNAN | INT | (i as u64 & POINTER_MASK))
+        } else {
+            let ptr = Box::into_raw(Box::new(i)) as u64;
+            debug_assert_eq!(ptr & !POINTER_MASK, 0, "pointer doesn't fit in 48 bits");
+            Value(SIGN_BIT | QNAN | INT | ptr)
+        }
+    }
+
+    pub fn object(obj: Object) -> Self {
+        let ptr = Box::into_raw(Box::new(obj)) as u64;
+        debug_assert_eq!(ptr & !POINTER_MASK, 0, "pointer doesn't fit in 48 bits");
+        Value(SIGN_BIT | QNAN | ptr)
+    }
+
+    pub fn is_falsey(&self) -> bool {
+        self.0 == NIL || self.0 == FALSE
+    }
+
+    pub fn is_nil(&self) -> bool {
+        self.0 == NIL
+    }
+
+    pub fn is_bool(&self) -> bool {
+        self.0 | 1 == TRUE
+    }
+
+    pub fn as_bool(&self) -> bool {
+        assert!(self.is_bool(), "self was not a boolean");
+        self.0 == TRUE
+    }
+
+    pub fn is_number(&self) -> bool {
+        self.0 & QNAN != QNAN
+    }
+
+    pub fn to_number(&self) -> f64 {
+        assert!(self.is_number(), "self was not a number");
+        f64::from_bits(self.0)
+    }
+
+    pub fn is_int(&self) -> bool {
+        self.0 & (QNAN | INT) == QNAN | INT
+    }
+
+    pub fn as_int(&self) -> i64 {
+        assert!(self.is_int(), "self was not an int");
+        if self.is_boxed_int() {
+            // Boxed ints are only ever created by `Value::int` and freed when the value is dropped
+            unsafe { *((self.0 & POINTER_MASK) as *const i64) }
+        } else {
+            // Sign extends the low 48 bits
+            ((self.0 << 16) as i64) >> 16
+        }
+    }
+
+    pub(crate) fn is_boxed_int(&self) -> bool {
+        self.0 & (SIGN_BIT | QNAN | INT) == SIGN_BIT | QNAN | INT
+    }
+
+    pub fn is_object(&self) -> bool {
+        self.0 & (SIGN_BIT | QNAN | INT) == SIGN_BIT | QNAN
+    }
+
+    pub fn as_object(&self) -> &Object {
+        assert!(self.is_object(), "self was not an Object");
+        // Objects are only ever created by `Value::object` and freed when the value is dropped
+        unsafe { &*((self.0 & POINTER_MASK) as *const Object) }
+    }
+
+    pub fn is_string(&self) -> bool {
+        self.is_object() && self.as_object().is_string()
+    }
+
+    pub fn as_string(&self) -> &str {
+        self.as_object().as_string()
+    }
+}
+
+impl Clone for Value {
+    fn clone(&self) -> Self {
+        if self.is_object() {
+            Value::object(self.as_object().clone())
+        } else if self.is_boxed_int() {
+            Value::int(self.as_int())
+        } else {
+            Value(self.0)
+        }
+    }
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        if self.is_object() {
+            // Same invariant as in `as_object`, and `self` is never used again
+            unsafe { drop(Box::from_raw((self.0 & POINTER_MASK) as *mut Object)) }
+        } else if self.is_boxed_int() {
+            // Same invariant as in `as_int`
+            unsafe { drop(Box::from_raw((self.0 & POINTER_MASK) as *mut i64)) }
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, rhs: &Value) -> bool {
+        if self.is_number() && rhs.is_number() {
+            self.to_number() == rhs.to_number()
+        } else if self.is_object() && rhs.is_object() {
+            self.as_object() == rhs.as_object()
+        } else if self.is_int() && rhs.is_int() {
+            self.as_int() == rhs.as_int()
+        } else {
+            self.0 == rhs.0
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_nil() {
+            write!(f, "Nil")
+        } else if self.is_bool() {
+            write!(f, "Boolean({:?})", self.as_bool())
+        } else if self.is_number() {
+            write!(f, "Number({:?})", self.to_number())
+        } else if self.is_int() {
+            write!(f, "Int({:?})", self.as_int())
+        } else {
+            write!(f, "Obj({:?})", self.as_object())
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_nil() {
+            write!(f, "nil")
+        } else if self.is_bool() {
+            write!(f, "{}", self.as_bool())
+        } else if self.is_number() {
+            arithmetic::write_float(f, self.to_number())
+        } else if self.is_int() {
+            write!(f, "{}", self.as_int())
+        } else {
+            write!(f, "{}", self.as_object())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_every_kind_of_value() {
+        assert!(Value::nil().is_nil());
+        assert!(Value::boolean(true).as_bool());
+        assert!(!Value::boolean(false).as_bool());
+        for n in [0.0, -0.0, 1.5, -1e300, f64::INFINITY, f64::NEG_INFINITY] {
+            let val = Value::number(n);
+            assert!(val.is_number() && !val.is_int() && !val.is_object());
+            assert_eq!(val.to_number().to_bits(), n.to_bits());
+        }
+        for i in [0, 1, -1, i64::MAX, i64::MIN] {
+            let val = Value::int(i);
+            assert!(val.is_int() && !val.is_number() && !val.is_object() && !val.is_bool());
+            assert_eq!(val.as_int(), i);
+        }
+        let val = Value::object(Object::String("kurisu".to_string()));
+        assert!(val.is_object() && val.is_string() && !val.is_int());
+        assert_eq!(val.as_string(), "kurisu");
+    }
+
+    #[test]
+    fn only_ints_beyond_48_bits_are_boxed() {
+        let limit = 1 << 47;
+        for i in [limit - 1, -limit] {
+            let val = Value::int(i);
+            assert!(!val.is_boxed_int());
+            assert_eq!(val.as_int(), i);
+        }
+        for i in [limit, -limit - 1, i64::MAX, i64::MIN] {
+            let val = Value::int(i);
+            assert!(val.is_boxed_int());
+            assert_eq!(val.as_int(), i);
+        }
+    }
+
+    #[test]
+    fn clones_own_their_own_allocation() {
+        let list = Object::List(vec![Value::int(1), Value::int(i64::MAX)]);
+        let original = Value::object(list.clone());
+        let copy = original.clone();
+        assert_ne!(original.0, copy.0);
+        drop(original);
+        assert_eq!(copy.as_object(), &list);
+
+        let original = Value::int(i64::MIN);
+        let copy = original.clone();
+        assert_ne!(original.0, copy.0);
+        drop(original);
+        assert_eq!(copy.as_int(), i64::MIN);
+    }
+
+    #[test]
+    fn nans_are_canonicalised() {
+        // NaNs whose bits would otherwise read as a boolean, an int or an object pointer
+        for bits in [TRUE, QNAN | INT | 5, SIGN_BIT | QNAN | 8] {
+            let val = Value::number(f64::from_bits(bits));
+            assert!(val.is_number());
+            assert!(!val.is_bool() && !val.is_int() && !val.is_object());
+            assert!(val.to_number().is_nan());
+        }
+    }
+}
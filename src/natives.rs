@@ -0,0 +1,62 @@
+//! The functions every script can call
+
+use crate::object::{Native, NativeError, Object};
+use crate::value::Value;
+use std::convert::TryFrom;
+use std::env;
+
+pub const NATIVES: [Native; 3] = [
+    Native {
+        name: "getenv",
+        arity: 1,
+        function: getenv,
+    },
+    Native {
+        name: "exit",
+        arity: 1,
+        function: exit,
+    },
+    Native {
+        name: "len",
+        arity: 1,
+        function: len,
+    },
+];
+
+/// `getenv(name)`, the value of an environment variable or `nil` if it isn't set
+fn getenv(args: &[Value]) -> Result<Value, NativeError> {
+    if !args[0].is_string() {
+        return Err(NativeError::Runtime(
+            "getenv expects the name of a variable".to_string(),
+        ));
+    }
+    Ok(match env::var(args[0].as_string()) {
+        Ok(val) => Value::object(Object::String(val)),
+        Err(_) => Value::nil(),
+    })
+}
+
+/// `exit(code)`, stops the script, the host decides what the code means
+fn exit(args: &[Value]) -> Result<Value, NativeError> {
+    let code = &args[0];
+    match code.is_int().then(|| i32::try_from(code.as_int())) {
+        Some(Ok(code)) => Err(NativeError::Exit(code)),
+        _ => Err(NativeError::Runtime(
+            "exit expects an integer that fits in 32 bits".to_string(),
+        )),
+    }
+}
+
+/// `len(value)`, the number of items in a list or bytes in a string
+fn len(args: &[Value]) -> Result<Value, NativeError> {
+    let val = &args[0];
+    let len = match val.is_object().then(|| val.as_object()) {
+        Some(Object::String(stri)) => stri.len(),
+        Some(Object::List(items)) => items.len(),
+        _ => {
+            let msg = "len expects a list or string".to_string();
+            return Err(NativeError::Runtime(msg));
+        }
+    };
+    Ok(Value::int(len as i64))
+}
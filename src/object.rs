@@ -1,22 +1,22 @@
+use crate::value::Value;
 use std::fmt;
 
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Object {
     String(String),
+    List(Vec<Value>),
+    Native(Native),
 }
 
 impl Object {
     pub fn is_string(&self) -> bool {
-        match self {
-            Object::String(_) => true,
-            _ => false,
-        }
+        matches!(self, Object::String(_))
     }
 
     pub fn as_string(&self) -> &str {
         match self {
             Object::String(stri) => stri,
-            _ => panic!("self was not a String"),
+            _ => panic!("self was not a string"),
         }
     }
 }
@@ -25,6 +25,49 @@ impl fmt::Display for Object {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Object::String(stri) => write!(f, "{}", stri),
+            Object::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Object::Native(native) => write!(f, "<native fn {}>", native.name),
         }
     }
 }
+
+/// Why a native function failed
+#[derive(Debug)]
+pub enum NativeError {
+    /// Reported like any other runtime error
+    Runtime(String),
+    /// The script asked to stop with the given exit code
+    Exit(i32),
+}
+
+pub type NativeFn = fn(&[Value]) -> Result<Value, NativeError>;
+
+/// A function implemented in Rust, it's only called with exactly `arity` arguments
+#[derive(Copy, Clone)]
+pub struct Native {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl PartialEq for Native {
+    fn eq(&self, rhs: &Native) -> bool {
+        // Natives are defined once, so the name is enough to tell them apart
+        self.name == rhs.name
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
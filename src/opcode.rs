@@ -1,6 +1,6 @@
 use std::fmt;
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum OpCode {
     Unknown = 0,
     Return,
@@ -18,10 +18,39 @@ pub enum OpCode {
     Equal,
     Greater,
     Less,
+    NotEqual,
+    GreaterEqual,
+    LessEqual,
+    Pop,
+    // The global's name is a constant, its 4 byte index is the operand
+    DefineGlobal,
+    GetGlobal,
+    SetGlobal,
+    Call, // The operand is the number of arguments
+    Index,
+    ToString,
+    FloorDivide,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    BitNot,
 }
 
-impl From<u8> for OpCode {
-    fn from(orig: u8) -> Self {
+impl OpCode {
+    /// The number of bytes taken up by the instruction, operands included
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Constant | Self::Call => 2,
+            Self::ConstantLong | Self::DefineGlobal | Self::GetGlobal | Self::SetGlobal => 5,
+            _ => 1,
+        }
+    }
+
+    const fn decode(orig: u8) -> Self {
         match orig {
             1 => Self::Return,
             2 => Self::Constant,
@@ -38,11 +67,47 @@ impl From<u8> for OpCode {
             13 => Self::Equal,
             14 => Self::Greater,
             15 => Self::Less,
+            16 => Self::NotEqual,
+            17 => Self::GreaterEqual,
+            18 => Self::LessEqual,
+            19 => Self::Pop,
+            20 => Self::DefineGlobal,
+            21 => Self::GetGlobal,
+            22 => Self::SetGlobal,
+            23 => Self::Call,
+            24 => Self::Index,
+            25 => Self::ToString,
+            26 => Self::FloorDivide,
+            27 => Self::Modulo,
+            28 => Self::Power,
+            29 => Self::BitAnd,
+            30 => Self::BitOr,
+            31 => Self::BitXor,
+            32 => Self::ShiftLeft,
+            33 => Self::ShiftRight,
+            34 => Self::BitNot,
             _ => Self::Unknown,
         }
     }
 }
 
+/// Maps every possible byte to the opcode it encodes, so decoding is a single lookup
+static DECODE_TABLE: [OpCode; 256] = {
+    let mut table = [OpCode::Unknown; 256];
+    let mut byte = 0;
+    while byte < table.len() {
+        table[byte] = OpCode::decode(byte as u8);
+        byte += 1;
+    }
+    table
+};
+
+impl From<u8> for OpCode {
+    fn from(orig: u8) -> Self {
+        DECODE_TABLE[orig as usize]
+    }
+}
+
 impl fmt::Display for OpCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self)
@@ -0,0 +1,1159 @@
+use crate::ast::{BinaryOp, Expr, ExprKind, Span, Stmt, StmtKind, UnaryOp};
+use crate::compiler::CompileError;
+use crate::object::Object;
+use crate::scanner::{Scanner, Token, TokenType};
+use crate::value::Value;
+use std::num::IntErrorKind;
+
+/// Parses `source` into a list of statements
+pub fn parse(source: &str) -> Result<Vec<Stmt>, Vec<CompileError>> {
+    let (program, errors) = parse_recovering(source);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(program)
+}
+
+/// Parses `source` like `parse`, but returns the statements that parsed cleanly along with
+/// the errors, so later passes can still check them
+pub fn parse_recovering(source: &str) -> (Vec<Stmt>, Vec<CompileError>) {
+    let mut parser = Parser::new(Scanner::new(source));
+
+    parser.advance(); // prime the parser
+
+    let mut program = Vec::new();
+    while !parser.matches(TokenType::Eof) {
+        let errors = parser.errors.len();
+        let stmt = declaration(&mut parser);
+        if parser.errors.len() == errors {
+            program.push(stmt);
+        }
+    }
+
+    (program, parser.errors)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+enum Precedence {
+    None = 0,
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
+    Term,
+    Factor,
+    Unary,
+    Power,
+    Call,
+    Primary,
+}
+
+impl From<u8> for Precedence {
+    fn from(val: u8) -> Self {
+        match val {
+            0 => Self::None,
+            1 => Self::Assignment,
+            2 => Self::Or,
+            3 => Self::And,
+            4 => Self::Equality,
+            5 => Self::Comparison,
+            6 => Self::BitOr,
+            7 => Self::BitXor,
+            8 => Self::BitAnd,
+            9 => Self::Shift,
+            10 => Self::Term,
+            11 => Self::Factor,
+            12 => Self::Unary,
+            13 => Self::Power,
+            14 => Self::Call,
+            15 => Self::Primary,
+            _ => unreachable!("bad precendence"),
+        }
+    }
+}
+
+fn declaration(parser: &mut Parser) -> Stmt {
+    let doc = parser.take_doc();
+    let mut stmt = if parser.matches(TokenType::Var) {
+        var_declaration(parser)
+    } else {
+        expression_statement(parser)
+    };
+    stmt.doc = doc;
+
+    if parser.panic_mode {
+        parser.synchronize();
+    }
+    stmt
+}
+
+fn var_declaration(parser: &mut Parser) -> Stmt {
+    let start = parser.previous_span();
+    parser.consume(TokenType::Identifier, "Expect variable name");
+    let name = parser.previous.name.clone();
+
+    let initializer = if parser.matches(TokenType::Equal) {
+        Some(expression(parser))
+    } else {
+        None
+    };
+    parser.consume(
+        TokenType::Semicolon,
+        "Expect ';' after variable declaration",
+    );
+
+    Stmt {
+        kind: StmtKind::Var { name, initializer },
+        span: start.to(parser.previous_span()),
+        doc: None,
+    }
+}
+
+/// The `;` may be left out after the last statement, so single expressions can be evaluated
+fn expression_statement(parser: &mut Parser) -> Stmt {
+    let expr = expression(parser);
+    if !parser.check(TokenType::Eof) {
+        parser.consume(TokenType::Semicolon, "Expect ';' after expression");
+    }
+
+    let span = expr.span;
+    Stmt {
+        kind: StmtKind::Expression(expr),
+        span,
+        doc: None,
+    }
+}
+
+fn expression(parser: &mut Parser) -> Expr {
+    parser.parse_precedence(Precedence::Assignment)
+}
+
+fn number(parser: &mut Parser) -> Expr {
+    let value = match parse_number(&parser.previous.name) {
+        Ok(value) => value,
+        Err(message) => {
+            parser.error(message);
+            Value::nil()
+        }
+    };
+    Expr::new(ExprKind::Literal(value), parser.previous_span())
+}
+
+/// The value of a number literal, an int unless it has a fraction or an exponent.
+/// Literals with a `0x`, `0b` or `0o` prefix are always ints, and `_` may be put
+/// between any two digits. A `.` must be followed by digits, so `1.` isn't a number
+fn parse_number(lexeme: &str) -> Result<Value, &'static str> {
+    const INVALID: &str = "Invalid number literal";
+    const OUT_OF_RANGE: &str = "Number literal is out of range";
+
+    let (radix, digits) = match lexeme.get(..2) {
+        Some("0x") => (16, &lexeme[2..]),
+        Some("0b") => (2, &lexeme[2..]),
+        Some("0o") => (8, &lexeme[2..]),
+        _ => (10, lexeme),
+    };
+
+    let is_digit = |c: Option<&u8>| c.is_some_and(|&c| (c as char).is_digit(radix));
+    let bytes = digits.as_bytes();
+    for (i, &c) in bytes.iter().enumerate() {
+        if c == b'_' && !(i > 0 && is_digit(bytes.get(i - 1)) && is_digit(bytes.get(i + 1))) {
+            return Err("'_' must be between two digits");
+        }
+    }
+    let digits = digits.replace('_', "");
+
+    let is_float = radix == 10 && digits.contains(['.', 'e', 'E']);
+    if let Some((_, fraction)) = digits.split_once('.') {
+        if !fraction.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(INVALID);
+        }
+    }
+    if !is_float {
+        return match i64::from_str_radix(&digits, radix) {
+            Ok(value) => Ok(Value::int(value)),
+            Err(e) if *e.kind() == IntErrorKind::PosOverflow => Err(OUT_OF_RANGE),
+            Err(_) => Err(INVALID),
+        };
+    }
+    match digits.parse::<f64>() {
+        Ok(value) if value.is_infinite() => Err(OUT_OF_RANGE),
+        Ok(value) => Ok(Value::number(value)),
+        Err(_) => Err(INVALID),
+    }
+}
+
+fn literal(parser: &mut Parser) -> Expr {
+    let value = match parser.previous.type_ {
+        TokenType::False => Value::boolean(false),
+        TokenType::True => Value::boolean(true),
+        TokenType::Nil => Value::nil(),
+        _ => unreachable!("not a literal"),
+    };
+    Expr::new(ExprKind::Literal(value), parser.previous_span())
+}
+
+fn string(parser: &mut Parser) -> Expr {
+    let lexeme = parser.previous.name.clone();
+    match lexeme.strip_prefix('r') {
+        Some(raw) => string_literal(parser, raw[1..raw.len() - 1].to_string()),
+        None => string_part(parser, &lexeme[1..lexeme.len() - 1]),
+    }
+}
+
+/// `"a ${b} c"` becomes `"a " + ToString(b) + " c"`, empty parts of the string are left out
+fn interpolation(parser: &mut Parser) -> Expr {
+    let mut parts = Vec::new();
+    loop {
+        // The previous token is `"a ${` or `} a ${`
+        let lexeme = parser.previous.name.clone();
+        if lexeme.len() > 3 {
+            parts.push(string_part(parser, &lexeme[1..lexeme.len() - 2]));
+        }
+
+        if parser.check_interpolation_end() {
+            parser.error_at_current("Expect expression");
+            break;
+        }
+        let inner = expression(parser);
+        let span = inner.span;
+        parts.push(Expr::new(
+            ExprKind::Unary {
+                op: UnaryOp::ToString,
+                op_span: span,
+                operand: Box::new(inner),
+            },
+            span,
+        ));
+
+        if parser.matches(TokenType::Interpolation) {
+            continue;
+        }
+        if !parser.check_interpolation_end() {
+            parser.error_at_current("Expect '}' after interpolated expression");
+            break;
+        }
+        parser.advance();
+        let lexeme = parser.previous.name.clone();
+        if lexeme.len() > 2 {
+            parts.push(string_part(parser, &lexeme[1..lexeme.len() - 1]));
+        }
+        break;
+    }
+
+    let mut parts = parts.into_iter();
+    let first = match parts.next() {
+        Some(first) => first,
+        // Never compiled, as the error makes `parse` fail
+        None => return Expr::new(ExprKind::Literal(Value::nil()), parser.previous_span()),
+    };
+    parts.fold(first, |lhs, rhs| {
+        let span = lhs.span.to(rhs.span);
+        Expr::new(
+            ExprKind::Binary {
+                op: BinaryOp::Add,
+                op_span: rhs.span,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            },
+            span,
+        )
+    })
+}
+
+/// A literal for `text`, a piece of the previous token with its escape sequences still in it
+fn string_part(parser: &mut Parser, text: &str) -> Expr {
+    match unescape(text) {
+        Ok(stri) => string_literal(parser, stri),
+        Err(message) => {
+            parser.error(&message);
+            string_literal(parser, String::new())
+        }
+    }
+}
+
+fn string_literal(parser: &Parser, stri: String) -> Expr {
+    Expr::new(
+        ExprKind::Literal(Value::object(Object::String(stri))),
+        parser.previous_span(),
+    )
+}
+
+/// Replaces the escape sequences in the contents of a string literal
+fn unescape(text: &str) -> Result<String, String> {
+    let mut stri = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            stri.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('$') => '$',
+            Some('0') => '\0',
+            Some('u') => unicode_escape(&mut chars)?,
+            Some(other) => return Err(format!("Invalid escape sequence '\\{}'", other)),
+            None => unreachable!("the scanner doesn't end a string after a backslash"),
+        };
+        stri.push(escaped);
+    }
+    Ok(stri)
+}
+
+/// The character of a `\u{...}` escape, with `chars` just after the `u`
+fn unicode_escape(chars: &mut std::str::Chars) -> Result<char, String> {
+    const MESSAGE: &str =
+        "Invalid unicode escape, expected '\\u{' followed by 1 to 6 hex digits and '}'";
+    if chars.next() != Some('{') {
+        return Err(MESSAGE.to_string());
+    }
+    let mut digits = String::new();
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some(c) if c.is_ascii_hexdigit() && digits.len() < 6 => digits.push(c),
+            _ => return Err(MESSAGE.to_string()),
+        }
+    }
+    let code = u32::from_str_radix(&digits, 16).map_err(|_| MESSAGE.to_string())?;
+    char::from_u32(code).ok_or_else(|| format!("'\\u{{{}}}' isn't a unicode scalar value", digits))
+}
+
+fn variable(parser: &mut Parser) -> Expr {
+    Expr::new(
+        ExprKind::Variable(parser.previous.name.clone()),
+        parser.previous_span(),
+    )
+}
+
+/// Parsed as a right associative infix operator with the lowest precedence,
+/// so any other operator on its left leaves something that can't be assigned to
+fn assignment(parser: &mut Parser, target: Expr) -> Expr {
+    let name = match target.kind {
+        ExprKind::Variable(name) => name,
+        _ => {
+            parser.error("Invalid assignment target");
+            String::new()
+        }
+    };
+
+    let value = parser.parse_precedence(Precedence::Assignment);
+
+    let span = target.span.to(value.span);
+    Expr::new(
+        ExprKind::Assign {
+            name,
+            value: Box::new(value),
+        },
+        span,
+    )
+}
+
+fn call(parser: &mut Parser, callee: Expr) -> Expr {
+    let mut args = Vec::new();
+    if !parser.check(TokenType::RightParen) {
+        loop {
+            if args.len() == u8::MAX as usize {
+                parser.error_at_current("Can't have more than 255 arguments");
+            }
+            args.push(expression(parser));
+            if !parser.matches(TokenType::Comma) {
+                break;
+            }
+        }
+    }
+    parser.consume(TokenType::RightParen, "Expect ')' after arguments");
+
+    let span = callee.span.to(parser.previous_span());
+    Expr::new(
+        ExprKind::Call {
+            callee: Box::new(callee),
+            args,
+        },
+        span,
+    )
+}
+
+fn index(parser: &mut Parser, target: Expr) -> Expr {
+    let index = expression(parser);
+    parser.consume(TokenType::RightBracket, "Expect ']' after index");
+
+    let span = target.span.to(parser.previous_span());
+    Expr::new(
+        ExprKind::Index {
+            target: Box::new(target),
+            index: Box::new(index),
+        },
+        span,
+    )
+}
+
+fn grouping(parser: &mut Parser) -> Expr {
+    let open = parser.previous_span();
+    let inner = expression(parser);
+    parser.consume(TokenType::RightParen, "Expect ')' after expression");
+    Expr::new(
+        ExprKind::Grouping(Box::new(inner)),
+        open.to(parser.previous_span()),
+    )
+}
+
+fn unary(parser: &mut Parser) -> Expr {
+    let op_span = parser.previous_span();
+    let op = match parser.previous.type_ {
+        TokenType::Minus => UnaryOp::Negate,
+        TokenType::Bang => UnaryOp::Not,
+        TokenType::Tilde => UnaryOp::BitNot,
+        _ => unreachable!("not a unary operator"),
+    };
+
+    let operand = parser.parse_precedence(Precedence::Unary);
+
+    let span = op_span.to(operand.span);
+    Expr::new(
+        ExprKind::Unary {
+            op,
+            op_span,
+            operand: Box::new(operand),
+        },
+        span,
+    )
+}
+
+fn binary(parser: &mut Parser, lhs: Expr) -> Expr {
+    let operator_type = parser.previous.type_;
+    let op_span = parser.previous_span();
+    let op = match operator_type {
+        TokenType::Plus => BinaryOp::Add,
+        TokenType::Minus => BinaryOp::Subtract,
+        TokenType::Star => BinaryOp::Multiply,
+        TokenType::Slash => BinaryOp::Divide,
+        TokenType::TildeSlash => BinaryOp::FloorDivide,
+        TokenType::Percent => BinaryOp::Modulo,
+        TokenType::StarStar => BinaryOp::Power,
+        TokenType::Ampersand => BinaryOp::BitAnd,
+        TokenType::Pipe => BinaryOp::BitOr,
+        TokenType::Caret => BinaryOp::BitXor,
+        TokenType::LessLess => BinaryOp::ShiftLeft,
+        TokenType::GreaterGreater => BinaryOp::ShiftRight,
+        TokenType::BangEqual => BinaryOp::NotEqual,
+        TokenType::EqualEqual => BinaryOp::Equal,
+        TokenType::Greater => BinaryOp::Greater,
+        TokenType::GreaterEqual => BinaryOp::GreaterEqual,
+        TokenType::Less => BinaryOp::Less,
+        TokenType::LessEqual => BinaryOp::LessEqual,
+        _ => unreachable!("not a binary operator"),
+    };
+
+    let rule = &RULES[operator_type as usize];
+    // `**` is right associative, so its right operand may contain another `**`
+    let rhs = if op == BinaryOp::Power {
+        parser.parse_precedence(rule.precedence)
+    } else {
+        parser.parse_precedence((rule.precedence as u8 + 1).into())
+    };
+
+    let span = lhs.span.to(rhs.span);
+    Expr::new(
+        ExprKind::Binary {
+            op,
+            op_span,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        },
+        span,
+    )
+}
+
+type PrefixFn = fn(&mut Parser) -> Expr;
+type InfixFn = fn(&mut Parser, Expr) -> Expr;
+
+struct ParseRule {
+    prefix: Option<PrefixFn>,
+    infix: Option<InfixFn>,
+    precedence: Precedence,
+}
+
+static RULES: [ParseRule; 54] = [
+    ParseRule {
+        // TokenType::LeftParen
+        prefix: Some(grouping),
+        infix: Some(call),
+        precedence: Precedence::Call,
+    },
+    ParseRule {
+        // TokenType::RightParen
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::LeftBrace
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::RightBrace
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::LeftBracket
+        prefix: None,
+        infix: Some(index),
+        precedence: Precedence::Call,
+    },
+    ParseRule {
+        // TokenType::RightBracket
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Comma
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Dot
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Minus
+        prefix: Some(unary),
+        infix: Some(binary),
+        precedence: Precedence::Term,
+    },
+    ParseRule {
+        // TokenType::Plus
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Term,
+    },
+    ParseRule {
+        // TokenType::Semicolon
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Slash
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Factor,
+    },
+    ParseRule {
+        // TokenType::Star
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Factor,
+    },
+    ParseRule {
+        // TokenType::Percent
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Factor,
+    },
+    ParseRule {
+        // TokenType::Ampersand
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::BitAnd,
+    },
+    ParseRule {
+        // TokenType::Pipe
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::BitOr,
+    },
+    ParseRule {
+        // TokenType::Caret
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::BitXor,
+    },
+    ParseRule {
+        // TokenType::Tilde
+        prefix: Some(unary),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Bang
+        prefix: Some(unary),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::BangEqual
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Equality,
+    },
+    ParseRule {
+        // TokenType::Equal
+        prefix: None,
+        infix: Some(assignment),
+        precedence: Precedence::Assignment,
+    },
+    ParseRule {
+        // TokenType::EqualEqual
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Equality,
+    },
+    ParseRule {
+        // TokenType::Greater
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Comparison,
+    },
+    ParseRule {
+        // TokenType::GreaterEqual
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Comparison,
+    },
+    ParseRule {
+        // TokenType::Less
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Comparison,
+    },
+    ParseRule {
+        // TokenType::LessEqual
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Comparison,
+    },
+    ParseRule {
+        // TokenType::TildeSlash
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Factor,
+    },
+    ParseRule {
+        // TokenType::StarStar
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Power,
+    },
+    ParseRule {
+        // TokenType::LessLess
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Shift,
+    },
+    ParseRule {
+        // TokenType::GreaterGreater
+        prefix: None,
+        infix: Some(binary),
+        precedence: Precedence::Shift,
+    },
+    ParseRule {
+        // TokenType::Identifier
+        prefix: Some(variable),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::String
+        prefix: Some(string),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Interpolation
+        prefix: Some(interpolation),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Number
+        prefix: Some(number),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::DocComment
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::And
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Class
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Else
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::False
+        prefix: Some(literal),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::For
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Fun
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::If
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Nil
+        prefix: Some(literal),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Or
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Print
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Return
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Super
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::This
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::True
+        prefix: Some(literal),
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Var
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::While
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Error
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::Eof
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+    ParseRule {
+        // TokenType::DefaultConstructed
+        prefix: None,
+        infix: None,
+        precedence: Precedence::None,
+    },
+];
+
+struct Parser<'a> {
+    previous: Token,
+    current: Token,
+    current_doc: Vec<Token>, // The doc comments right before `current`
+    errors: Vec<CompileError>,
+    panic_mode: bool,
+    scanner: Scanner<'a>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(scanner: Scanner<'a>) -> Self {
+        Parser {
+            previous: Token::new(),
+            current: Token::new(),
+            current_doc: Vec::new(),
+            scanner,
+            errors: Vec::new(),
+            panic_mode: false,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.previous = self.current.clone();
+        self.current_doc.clear();
+
+        loop {
+            self.current = self.scanner.scan_token();
+            match self.current.type_ {
+                TokenType::DocComment => self.current_doc.push(self.current.clone()),
+                TokenType::Error => {
+                    // The lexeme of an error token is the error message
+                    let message = self.current.name.clone();
+                    self.error_at_current(&message);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// The text of the doc comments before the current token, which are only
+    /// kept when that token starts a declaration
+    fn take_doc(&mut self) -> Option<String> {
+        if self.current_doc.is_empty() {
+            return None;
+        }
+        let lines: Vec<&str> = self
+            .current_doc
+            .iter()
+            .map(|token| {
+                let text = &token.name[3..];
+                text.strip_prefix(' ').unwrap_or(text)
+            })
+            .collect();
+        let doc = lines.join("\n");
+        self.current_doc.clear();
+        Some(doc)
+    }
+
+    fn consume(&mut self, type_: TokenType, message: &str) {
+        if self.current.type_ == type_ {
+            self.advance();
+            return;
+        }
+        self.error_at_current(message);
+    }
+
+    fn check(&self, type_: TokenType) -> bool {
+        self.current.type_ == type_
+    }
+
+    /// Whether the current token is the rest of an interpolated string, the only
+    /// string token that starts with a `}`
+    fn check_interpolation_end(&self) -> bool {
+        self.check(TokenType::String) && self.current.name.starts_with('}')
+    }
+
+    fn matches(&mut self, type_: TokenType) -> bool {
+        if !self.check(type_) {
+            return false;
+        }
+        self.advance();
+        true
+    }
+
+    /// Skips tokens until the start of the next statement, so that one error
+    /// doesn't cause a cascade of others
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+
+        while self.current.type_ != TokenType::Eof {
+            if self.previous.type_ == TokenType::Semicolon {
+                return;
+            }
+            match self.current.type_ {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    fn parse_precedence(&mut self, p: Precedence) -> Expr {
+        self.advance();
+        let prefix_rule = match RULES[self.previous.type_ as usize].prefix {
+            Some(rule) => rule,
+            None => {
+                self.error("Expect expression");
+                // Never compiled, as the error makes `parse` fail
+                return Expr::new(ExprKind::Literal(Value::nil()), self.previous_span());
+            }
+        };
+
+        let mut expr = prefix_rule(self);
+
+        while p <= RULES[self.current.type_ as usize].precedence {
+            self.advance();
+            let infix_rule = RULES[self.previous.type_ as usize].infix;
+            expr = infix_rule.unwrap()(self, expr);
+        }
+
+        expr
+    }
+
+    fn previous_span(&self) -> Span {
+        Span::from(&self.previous)
+    }
+
+    fn error_at_current(&mut self, message: &str) {
+        let token = self.current.clone();
+        self.error_at(&token, message);
+    }
+
+    fn error(&mut self, message: &str) {
+        let token = self.previous.clone();
+        self.error_at(&token, message)
+    }
+
+    fn error_at(&mut self, token: &Token, message: &str) {
+        if self.panic_mode {
+            return;
+        }
+        self.panic_mode = true;
+
+        let location = match token.type_ {
+            TokenType::Eof => " at end".to_string(),
+            TokenType::Error => String::new(),
+            _ => format!(" at {}", token.name),
+        };
+
+        self.errors.push(CompileError {
+            line: token.line,
+            location,
+            message: message.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{VMError, VM};
+
+    /// Runs `source`, which must end with an expression, and returns its value
+    fn evaluate(source: &str) -> Value {
+        let chunk = crate::compiler::compile(source).unwrap_or_else(|e| panic!("{:?}", e));
+        VM::new().execute(chunk).unwrap()
+    }
+
+    /// The messages of the errors parsing `source` reports
+    fn errors(source: &str) -> Vec<String> {
+        match parse(source) {
+            Ok(_) => panic!("{} parsed without errors", source),
+            Err(errors) => errors.into_iter().map(|e| e.message).collect(),
+        }
+    }
+
+    fn string(stri: &str) -> Value {
+        Value::object(Object::String(stri.to_string()))
+    }
+
+    #[test]
+    fn number_literals() {
+        let int = |i| Ok(Value::int(i));
+        assert_eq!(parse_number("0xFF"), int(255));
+        assert_eq!(parse_number("0b1010"), int(10));
+        assert_eq!(parse_number("0o17"), int(15));
+        assert_eq!(parse_number("1_000"), int(1000));
+        assert_eq!(parse_number("0x7FFF_FFFF_FFFF_FFFF"), int(i64::MAX));
+        assert_eq!(parse_number("1e-9"), Ok(Value::number(1e-9)));
+        assert_eq!(parse_number("1.5E3"), Ok(Value::number(1500.0)));
+        assert_eq!(parse_number("2.0"), Ok(Value::number(2.0)));
+    }
+
+    #[test]
+    fn operator_precedence_and_associativity() {
+        for (source, value) in [
+            ("2 ** 3 ** 2", Value::int(512)),
+            ("-2 ** 2", Value::int(-4)),
+            ("(-2) ** 2", Value::int(4)),
+            ("2 * 3 ** 2", Value::int(18)),
+            ("10 - 4 - 3", Value::int(3)),
+            ("1 | 2 ^ 3 & 4", Value::int(3)),
+            ("6 & 3 | 8", Value::int(10)),
+            ("6 ^ 3 | 8", Value::int(13)),
+            ("1 << 2 + 1", Value::int(8)),
+            ("1 + 16 >> 2", Value::int(4)),
+            ("1 << 2 < 5", Value::boolean(true)),
+        ] {
+            assert!(evaluate(source) == value, "{}", source);
+        }
+    }
+
+    #[test]
+    fn shifts_outside_the_range_of_an_int_fail() {
+        assert!(evaluate("1 << 63") == Value::int(i64::MIN));
+        assert!(evaluate("-8 >> 63") == Value::int(-1));
+        for amount in ["64", "-1", "1 << 40"] {
+            let source = format!("var n = {}; 1 << n", amount);
+            let chunk = crate::compiler::compile(&source).unwrap();
+            let result = VM::new().execute(chunk);
+            assert!(matches!(result, Err(VMError::Runtime)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn malformed_number_literals() {
+        const MISPLACED: &str = "'_' must be between two digits";
+        for (lexeme, error) in [
+            ("1__0", MISPLACED),
+            ("1_", MISPLACED),
+            ("0x_1", MISPLACED),
+            ("1_.5", MISPLACED),
+            ("0x", "Invalid number literal"),
+            ("0xFG", "Invalid number literal"),
+            ("0b102", "Invalid number literal"),
+            ("1e", "Invalid number literal"),
+            ("1.", "Invalid number literal"),
+            ("1.e5", "Invalid number literal"),
+            ("99999999999999999999", "Number literal is out of range"),
+            ("0x1_0000_0000_0000_0000", "Number literal is out of range"),
+            ("1e400", "Number literal is out of range"),
+        ] {
+            assert_eq!(parse_number(lexeme), Err(error), "{}", lexeme);
+        }
+    }
+
+    #[test]
+    fn interpolations() {
+        assert_eq!(evaluate("\"a ${ \"b ${1}\" } c\""), string("a b 1 c"));
+        assert_eq!(evaluate("var x = 2;\n\"${x}${x + 1}\""), string("23"));
+        assert_eq!(evaluate("\"\\${x}\""), string("${x}"));
+        assert_eq!(evaluate("\"${2.0}\""), string("2.0"));
+    }
+
+    #[test]
+    fn malformed_interpolations() {
+        assert_eq!(errors("\"${}\";"), ["Expect expression"]);
+        assert_eq!(
+            errors("\"${1 2}\";"),
+            ["Expect '}' after interpolated expression"]
+        );
+        assert_eq!(
+            errors("\"${1"),
+            ["Expect '}' after interpolated expression"]
+        );
+        assert_eq!(errors("\"${1}"), ["Unterminated string"]);
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let escaped = unescape(r#"\n\t\r\\\"\$\0"#).unwrap();
+        assert_eq!(escaped, "\n\t\r\\\"$\0");
+        assert_eq!(
+            unescape(r"\u{1F600} \u{e9}\u{0}").unwrap(),
+            "\u{1F600} \u{e9}\u{0}"
+        );
+        assert_eq!(unescape(r"\u{10FFFF}").unwrap(), "\u{10FFFF}");
+        assert_eq!(evaluate(r#""tab\there""#), string("tab\there"));
+    }
+
+    #[test]
+    fn malformed_escape_sequences() {
+        const MALFORMED: &str =
+            "Invalid unicode escape, expected '\\u{' followed by 1 to 6 hex digits and '}'";
+        assert_eq!(unescape(r"\u{}"), Err(MALFORMED.to_string()));
+        assert_eq!(unescape(r"\u{01F6000}"), Err(MALFORMED.to_string()));
+        assert_eq!(unescape(r"\u1F600"), Err(MALFORMED.to_string()));
+        assert_eq!(unescape(r"\u{1F600"), Err(MALFORMED.to_string()));
+        assert_eq!(unescape(r"\u{xyz}"), Err(MALFORMED.to_string()));
+        assert_eq!(
+            unescape(r"\u{D800}"),
+            Err("'\\u{D800}' isn't a unicode scalar value".to_string())
+        );
+        assert_eq!(
+            unescape(r"\u{110000}"),
+            Err("'\\u{110000}' isn't a unicode scalar value".to_string())
+        );
+        assert_eq!(
+            unescape(r"\q"),
+            Err("Invalid escape sequence '\\q'".to_string())
+        );
+        assert_eq!(errors(r#""\q";"#), ["Invalid escape sequence '\\q'"]);
+    }
+
+    #[test]
+    fn raw_strings_keep_backslashes() {
+        assert_eq!(evaluate(r#"r"a\nb\q\u{}""#), string(r"a\nb\q\u{}"));
+        assert_eq!(evaluate(r#"r"${1}""#), string("${1}"));
+        assert_eq!(evaluate(r#"r"""#), string(""));
+    }
+
+    fn docs(source: &str) -> Vec<Option<String>> {
+        parse(source)
+            .unwrap_or_else(|e| panic!("{:?}", e))
+            .into_iter()
+            .map(|stmt| stmt.doc)
+            .collect()
+    }
+
+    #[test]
+    fn doc_comments_are_attached_to_the_following_statement() {
+        let source = "/// The answer\n///   indented\nvar x = 42;\nvar y;\n/// A doc\nx;";
+        assert_eq!(
+            docs(source),
+            [
+                Some("The answer\n  indented".to_string()),
+                None,
+                Some("A doc".to_string())
+            ]
+        );
+        assert_eq!(docs("//// Not a doc\n// Nor this\nvar x;"), [None]);
+        assert_eq!(docs("/* /// Inside a comment */ var x;"), [None]);
+    }
+
+    #[test]
+    fn doc_comments_inside_statements_are_dropped() {
+        assert_eq!(docs("var x = /// Misplaced\n1;\nvar y;"), [None, None]);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::chunk::Chunk;
+use crate::opcode::OpCode;
+
+/// An instruction as it will be written back into the chunk
+struct Instruction {
+    op: OpCode,
+    bytes: Vec<u8>,
+    line: u32,
+}
+
+/// Rewrites common instruction sequences in `chunk` into shorter equivalents:
+///  * `Equal; Not`, `Less; Not` and `Greater; Not` become `NotEqual`, `GreaterEqual` and `LessEqual`
+///  * `Not; Not` is removed when the value being negated is already a boolean
+///  * a literal load immediately followed by a `Pop` is removed
+///
+/// The chunk must not contain jumps, as their offsets aren't adjusted.
+pub fn optimize(chunk: &mut Chunk) {
+    let code = chunk.code();
+    let mut out: Vec<Instruction> = Vec::new();
+
+    let mut offset = 0usize;
+    while offset < code.len() {
+        let op: OpCode = code[offset].into();
+        let end = offset + op.size();
+        push(
+            &mut out,
+            Instruction {
+                op,
+                bytes: code[offset..end].to_vec(),
+                line: chunk.lines[offset],
+            },
+        );
+        offset = end;
+    }
+
+    let mut code = Vec::with_capacity(chunk.len());
+    let mut lines = Vec::with_capacity(chunk.len());
+    for instr in out {
+        lines.resize(lines.len() + instr.bytes.len(), instr.line);
+        code.extend(instr.bytes);
+    }
+    chunk.set_code(code, lines);
+}
+
+/// Appends `instr` to `out`, merging it with the previously emitted instructions where possible
+fn push(out: &mut Vec<Instruction>, instr: Instruction) {
+    let last = match out.last_mut() {
+        Some(last) => last,
+        None => {
+            out.push(instr);
+            return;
+        }
+    };
+
+    match (last.op, instr.op) {
+        (OpCode::Equal, OpCode::Not) => replace(last, OpCode::NotEqual),
+        (OpCode::Less, OpCode::Not) => replace(last, OpCode::GreaterEqual),
+        (OpCode::Greater, OpCode::Not) => replace(last, OpCode::LessEqual),
+        (OpCode::NotEqual, OpCode::Not) => replace(last, OpCode::Equal),
+        (OpCode::GreaterEqual, OpCode::Not) => replace(last, OpCode::Less),
+        (OpCode::LessEqual, OpCode::Not) => replace(last, OpCode::Greater),
+        (OpCode::Not, OpCode::Not) if out.len() > 1 && produces_boolean(out[out.len() - 2].op) => {
+            out.pop();
+        }
+        (OpCode::Constant, OpCode::Pop)
+        | (OpCode::ConstantLong, OpCode::Pop)
+        | (OpCode::Nil, OpCode::Pop)
+        | (OpCode::True, OpCode::Pop)
+        | (OpCode::False, OpCode::Pop) => {
+            out.pop();
+        }
+        _ => out.push(instr),
+    }
+}
+
+fn replace(instr: &mut Instruction, op: OpCode) {
+    instr.op = op;
+    instr.bytes = vec![op as u8];
+}
+
+fn produces_boolean(op: OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::True
+            | OpCode::False
+            | OpCode::Not
+            | OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::NotEqual
+            | OpCode::GreaterEqual
+            | OpCode::LessEqual
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+    use crate::vm::VM;
+
+    fn chunk(code: &[u8]) -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::number(f64::NAN));
+        chunk.add_constant(Value::int(1));
+        let lines = (1..=code.len() as u32).collect();
+        chunk.set_code(code.to_vec(), lines);
+        chunk
+    }
+
+    fn optimized(code: &[u8]) -> Vec<u8> {
+        let mut chunk = chunk(code);
+        optimize(&mut chunk);
+        chunk.code().to_vec()
+    }
+
+    fn ops(ops: &[OpCode]) -> Vec<u8> {
+        ops.iter().map(|&op| op as u8).collect()
+    }
+
+    #[test]
+    fn comparisons_absorb_a_following_not() {
+        use OpCode::*;
+        for (comparison, negated) in [
+            (Equal, NotEqual),
+            (Less, GreaterEqual),
+            (Greater, LessEqual),
+            (NotEqual, Equal),
+            (GreaterEqual, Less),
+            (LessEqual, Greater),
+        ] {
+            let code = ops(&[True, False, comparison, Not, Return]);
+            assert_eq!(optimized(&code), ops(&[True, False, negated, Return]));
+        }
+    }
+
+    #[test]
+    fn double_negations_of_booleans_are_removed() {
+        use OpCode::*;
+        let code = ops(&[True, False, Less, Not, Not, Return]);
+        assert_eq!(optimized(&code), ops(&[True, False, Less, Return]));
+        assert_eq!(
+            optimized(&ops(&[True, Not, Not, Return])),
+            ops(&[True, Return])
+        );
+
+        // `!!nil` is `false`, not `nil`
+        let code = ops(&[Nil, Not, Not, Return]);
+        assert_eq!(optimized(&code), code);
+        let code = [Constant as u8, 1, Not as u8, Not as u8, Return as u8];
+        assert_eq!(optimized(&code), code);
+    }
+
+    #[test]
+    fn unused_literals_are_removed() {
+        use OpCode::*;
+        for literal in [Nil, True, False] {
+            assert_eq!(
+                optimized(&ops(&[literal, Pop, Nil, Return])),
+                ops(&[Nil, Return])
+            );
+        }
+        let code = [Constant as u8, 1, Pop as u8, Nil as u8, Return as u8];
+        assert_eq!(optimized(&code), ops(&[Nil, Return]));
+        let code = [
+            ConstantLong as u8,
+            0,
+            0,
+            0,
+            1,
+            Pop as u8,
+            Nil as u8,
+            Return as u8,
+        ];
+        assert_eq!(optimized(&code), ops(&[Nil, Return]));
+    }
+
+    #[test]
+    fn merged_instructions_keep_their_lines() {
+        use OpCode::*;
+        let mut chunk = chunk(&ops(&[True, Pop, True, False, Equal, Not, Return]));
+        optimize(&mut chunk);
+        assert_eq!(chunk.code(), &ops(&[True, False, NotEqual, Return])[..]);
+        assert_eq!(chunk.lines, [3, 4, 5, 7]);
+    }
+
+    #[test]
+    fn nans_compare_the_same_once_merged() {
+        use OpCode::*;
+        // `NaN >= 1`, which is `!(NaN < 1)`
+        let code = [
+            Constant as u8,
+            0,
+            Constant as u8,
+            1,
+            Less as u8,
+            Not as u8,
+            Return as u8,
+        ];
+        let mut merged = chunk(&code);
+        optimize(&mut merged);
+        assert_eq!(merged.code()[4], GreaterEqual as u8);
+
+        let mut vm = VM::new();
+        let expected = Value::boolean(true);
+        assert_eq!(vm.execute(chunk(&code)).unwrap(), expected);
+        assert_eq!(vm.execute(merged).unwrap(), expected);
+
+        // Folded and at runtime
+        for source in ["0.0 / 0.0 >= 1", "var z = 0.0;\nz / z >= 1"] {
+            let chunk = crate::compiler::compile(source).unwrap();
+            assert_eq!(vm.execute(chunk).unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! The instruction set of the register based backend. Instructions are three-address,
+//! e.g. `Add r0, r1, k2`, and read their operands either from a register or straight
+//! from the constant pool, so literals never need to be loaded separately.
+
+use crate::chunk::ConstantPool;
+use crate::value::Value;
+use std::fmt;
+use std::io::{self, Write};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Operand {
+    Register(u8),
+    Constant(u32),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Instruction {
+    Negate {
+        dst: u8,
+        src: Operand,
+    },
+    Not {
+        dst: u8,
+        src: Operand,
+    },
+    ToString {
+        dst: u8,
+        src: Operand,
+    },
+    BitNot {
+        dst: u8,
+        src: Operand,
+    },
+    Add {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Subtract {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Multiply {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Divide {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    FloorDivide {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Modulo {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Power {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    BitAnd {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    BitOr {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    BitXor {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    ShiftLeft {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    ShiftRight {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Equal {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    NotEqual {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Greater {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    GreaterEqual {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Less {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    LessEqual {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    /// `lhs[rhs]`
+    Index {
+        dst: u8,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    Move {
+        dst: u8,
+        src: Operand,
+    },
+    /// Calls the value in register `base` with the `argc` registers after it as arguments
+    Call {
+        dst: u8,
+        base: u8,
+        argc: u8,
+    },
+    Return {
+        src: Operand,
+    },
+    /// Globals are named by the index of a string constant
+    DefineGlobal {
+        name: u32,
+        src: Operand,
+    },
+    GetGlobal {
+        dst: u8,
+        name: u32,
+    },
+    SetGlobal {
+        name: u32,
+        src: Operand,
+    },
+}
+
+#[derive(Clone, Default)]
+pub struct RegisterChunk {
+    pub code: Vec<Instruction>,
+    pub constants: ConstantPool,
+    pub lines: Vec<u32>,
+    /// How many registers the code needs
+    pub registers: usize,
+    /// Whether the returned value is that of a trailing expression statement
+    pub returns_expression: bool,
+}
+
+impl RegisterChunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn append(&mut self, instr: Instruction, line: u32) {
+        self.code.push(instr);
+        self.lines.push(line);
+    }
+
+    pub fn add_constant(&mut self, val: Value) -> u32 {
+        self.constants.add(val)
+    }
+
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    pub fn disassemble(&self, name: &str, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "-== {} ==-", name)?;
+
+        for offset in 0..self.code.len() {
+            self.disassemble_instruction(offset, out)?;
+        }
+        Ok(())
+    }
+
+    pub fn disassemble_instruction(&self, offset: usize, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "o:{:04} ", offset)?;
+
+        if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
+            write!(out, "     | ")?;
+        } else {
+            write!(out, "l:{:04} ", self.lines[offset])?;
+        }
+
+        let operand = |op: Operand| match op {
+            Operand::Register(r) => format!("r{}", r),
+            Operand::Constant(k) => format!("k{} '{}'", k, self.constants[k as usize]),
+        };
+
+        match self.code[offset] {
+            Instruction::Negate { dst, src } => writeln!(out, "Negate r{}, {}", dst, operand(src)),
+            Instruction::Not { dst, src } => writeln!(out, "Not r{}, {}", dst, operand(src)),
+            Instruction::BitNot { dst, src } => writeln!(out, "BitNot r{}, {}", dst, operand(src)),
+            Instruction::ToString { dst, src } => {
+                writeln!(out, "ToString r{}, {}", dst, operand(src))
+            }
+            Instruction::Move { dst, src } => writeln!(out, "Move r{}, {}", dst, operand(src)),
+            Instruction::Call { dst, base, argc } => {
+                writeln!(out, "Call r{}, r{}, {}", dst, base, argc)
+            }
+            Instruction::Return { src } => writeln!(out, "Return {}", operand(src)),
+            Instruction::DefineGlobal { name, src } | Instruction::SetGlobal { name, src } => {
+                writeln!(
+                    out,
+                    "{} {}, {}",
+                    self.code[offset],
+                    operand(Operand::Constant(name)),
+                    operand(src)
+                )
+            }
+            Instruction::GetGlobal { dst, name } => writeln!(
+                out,
+                "GetGlobal r{}, {}",
+                dst,
+                operand(Operand::Constant(name))
+            ),
+            instr => {
+                let (dst, lhs, rhs) = instr.binary_operands().unwrap();
+                writeln!(
+                    out,
+                    "{} r{}, {}, {}",
+                    instr,
+                    dst,
+                    operand(lhs),
+                    operand(rhs)
+                )
+            }
+        }
+    }
+}
+
+impl Instruction {
+    /// Returns the destination and source operands of a binary instruction
+    pub fn binary_operands(&self) -> Option<(u8, Operand, Operand)> {
+        match *self {
+            Self::Add { dst, lhs, rhs }
+            | Self::Subtract { dst, lhs, rhs }
+            | Self::Multiply { dst, lhs, rhs }
+            | Self::Divide { dst, lhs, rhs }
+            | Self::FloorDivide { dst, lhs, rhs }
+            | Self::Modulo { dst, lhs, rhs }
+            | Self::Power { dst, lhs, rhs }
+            | Self::BitAnd { dst, lhs, rhs }
+            | Self::BitOr { dst, lhs, rhs }
+            | Self::BitXor { dst, lhs, rhs }
+            | Self::ShiftLeft { dst, lhs, rhs }
+            | Self::ShiftRight { dst, lhs, rhs }
+            | Self::Equal { dst, lhs, rhs }
+            | Self::NotEqual { dst, lhs, rhs }
+            | Self::Greater { dst, lhs, rhs }
+            | Self::GreaterEqual { dst, lhs, rhs }
+            | Self::Less { dst, lhs, rhs }
+            | Self::LessEqual { dst, lhs, rhs }
+            | Self::Index { dst, lhs, rhs } => Some((dst, lhs, rhs)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let debug = format!("{:?}", self);
+        // Only the name of the variant, i.e. `Add` for `Add { dst: 0, .. }`
+        write!(f, "{}", debug.split_whitespace().next().unwrap_or(""))
+    }
+}
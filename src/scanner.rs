@@ -1,10 +1,14 @@
 use std::fmt;
 
+const UNTERMINATED_STRING: &str = "Unterminated string";
+const UNTERMINATED_BLOCK_COMMENT: &str = "Unterminated block comment";
+
 pub struct Scanner<'a> {
     source: &'a str, // The source code
     start: usize,    // The start of the lexeme being examined
     current: usize,  // The character we are currently looking at
     line: usize,
+    interpolations: Vec<usize>, // Per `${` still open, the number of `{` opened inside it
 }
 
 impl<'a> Scanner<'a> {
@@ -14,11 +18,14 @@ impl<'a> Scanner<'a> {
             start: 0,
             current: 0,
             line: 1,
+            interpolations: Vec::new(),
         }
     }
 
     pub fn scan_token(&mut self) -> Token {
-        self.skip_whitespace_and_comments();
+        if let Some(error) = self.skip_whitespace_and_comments() {
+            return error;
+        }
 
         self.start = self.current;
         if self.is_at_end() {
@@ -30,14 +37,52 @@ impl<'a> Scanner<'a> {
         match c {
             '(' => self.make_token(TokenType::LeftParen),
             ')' => self.make_token(TokenType::RightParen),
-            '{' => self.make_token(TokenType::LeftBrace),
-            '}' => self.make_token(TokenType::RightBrace),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.make_token(TokenType::LeftBrace)
+            }
+            '}' => match self.interpolations.last_mut() {
+                // The end of an interpolated expression, the string carries on after it
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string_token(false)
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    self.make_token(TokenType::RightBrace)
+                }
+                None => self.make_token(TokenType::RightBrace),
+            },
+            '[' => self.make_token(TokenType::LeftBracket),
+            ']' => self.make_token(TokenType::RightBracket),
             ';' => self.make_token(TokenType::Semicolon),
             ',' => self.make_token(TokenType::Comma),
             '.' => self.make_token(TokenType::Dot),
             '+' => self.make_token(TokenType::Plus),
             '-' => self.make_token(TokenType::Minus),
-            '*' => self.make_token(TokenType::Star),
+            '*' => {
+                let token_type = if self.next_matches('*') {
+                    TokenType::StarStar
+                } else {
+                    TokenType::Star
+                };
+                self.make_token(token_type)
+            }
+            '%' => self.make_token(TokenType::Percent),
+            '&' => self.make_token(TokenType::Ampersand),
+            '|' => self.make_token(TokenType::Pipe),
+            '^' => self.make_token(TokenType::Caret),
+            '~' => {
+                let token_type = if self.next_matches('/') {
+                    TokenType::TildeSlash
+                } else {
+                    TokenType::Tilde
+                };
+                self.make_token(token_type)
+            }
+            '/' if self.is_doc_comment(self.start) => self.doc_comment_token(),
             '/' => self.make_token(TokenType::Slash),
             '!' => {
                 let token_type = if self.next_matches('=') {
@@ -58,6 +103,8 @@ impl<'a> Scanner<'a> {
             '>' => {
                 let token_type = if self.next_matches('=') {
                     TokenType::GreaterEqual
+                } else if self.next_matches('>') {
+                    TokenType::GreaterGreater
                 } else {
                     TokenType::Greater
                 };
@@ -66,23 +113,56 @@ impl<'a> Scanner<'a> {
             '<' => {
                 let token_type = if self.next_matches('=') {
                     TokenType::LessEqual
+                } else if self.next_matches('<') {
+                    TokenType::LessLess
                 } else {
                     TokenType::Less
                 };
                 self.make_token(token_type)
             }
-            '"' => self.string_token(),
+            '"' => self.string_token(false),
+            'r' if self.peek() == '"' => {
+                self.advance();
+                self.string_token(true)
+            }
             '0'..='9' => self.number_token(),
             'a'..='z' | 'A'..='Z' | '_' => self.identifier_token(),
             _ => self.error_token("Unexpected character."),
         }
     }
 
+    /// Whether `source` stops partway through something, so an interactive session should read
+    /// more of it. That's unclosed parentheses, braces or brackets, an unterminated string or
+    /// block comment, or an unfinished interpolation
+    pub fn is_incomplete(source: &str) -> bool {
+        let mut scanner = Scanner::new(source);
+        let mut depth = 0;
+        loop {
+            let token = scanner.scan_token();
+            match token.type_ {
+                TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth += 1,
+                TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => {
+                    depth -= 1
+                }
+                TokenType::Error
+                    if token.name == UNTERMINATED_STRING
+                        || token.name == UNTERMINATED_BLOCK_COMMENT =>
+                {
+                    return true
+                }
+                TokenType::Eof => return depth > 0 || !scanner.interpolations.is_empty(),
+                _ => (),
+            }
+        }
+    }
+
     fn make_token(&self, token_type: TokenType) -> Token {
         Token {
             type_: token_type,
             name: self.source[self.start..self.current].to_string(),
             line: self.line,
+            start: self.start,
+            end: self.current,
         }
     }
 
@@ -91,11 +171,28 @@ impl<'a> Scanner<'a> {
             type_: TokenType::Error,
             name: msg.to_string(),
             line: self.line,
+            start: self.start,
+            end: self.current,
         }
     }
 
-    fn string_token(&mut self) -> Token {
+    /// Escape sequences are left for the parser, here they only stop `\"` from ending the string.
+    /// `"a ${b} c"` is split into an `Interpolation` token `"a ${`, the tokens of `b`, and the
+    /// `String` token `} c"`, which is scanned by calling this again when the `}` is reached
+    fn string_token(&mut self, raw: bool) -> Token {
         while self.peek() != '"' && !self.is_at_end() {
+            if !raw && self.peek() == '$' && self.peek_next() == '{' {
+                self.advance();
+                self.advance();
+                self.interpolations.push(0);
+                return self.make_token(TokenType::Interpolation);
+            }
+            if !raw && self.peek() == '\\' {
+                self.advance();
+                if self.is_at_end() {
+                    break;
+                }
+            }
             if self.peek() == '\n' {
                 self.line += 1;
             }
@@ -103,7 +200,7 @@ impl<'a> Scanner<'a> {
         }
 
         if self.is_at_end() {
-            return self.error_token("Unterminated string");
+            return self.error_token(UNTERMINATED_STRING);
         }
 
         // The closing quote
@@ -111,23 +208,50 @@ impl<'a> Scanner<'a> {
         self.make_token(TokenType::String)
     }
 
-    fn number_token(&mut self) -> Token {
-        while self.peek().is_digit(10) {
+    /// `/// text` up to the end of the line, the leading `/` has already been consumed
+    fn doc_comment_token(&mut self) -> Token {
+        while self.peek() != '\n' && !self.is_at_end() {
             self.advance();
         }
-        // look ma', I have a fractional part
-        if self.peek() == '.' {
-            // Consume the dot
+        self.make_token(TokenType::DocComment)
+    }
+
+    /// The parser checks the digits, here a number is only split from what's around it
+    fn number_token(&mut self) -> Token {
+        let prefixed =
+            self.source[self.start..].starts_with('0') && matches!(self.peek(), 'x' | 'b' | 'o');
+        if prefixed {
             self.advance();
-            while self.peek().is_digit(10) {
+        } else {
+            self.digits();
+            // look ma', I have a fractional part
+            if self.peek() == '.' {
+                // Consume the dot
                 self.advance();
+                self.digits();
             }
+            if matches!(self.peek(), 'e' | 'E') {
+                self.advance();
+                if matches!(self.peek(), '+' | '-') {
+                    self.advance();
+                }
+            }
+        }
+        // Letters stuck to the number are kept in it, so `0xFG` or `1e` are reported as malformed
+        while self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
+            self.advance();
         }
         self.make_token(TokenType::Number)
     }
 
+    fn digits(&mut self) {
+        while self.peek().is_ascii_digit() || self.peek() == '_' {
+            self.advance();
+        }
+    }
+
     fn identifier_token(&mut self) -> Token {
-        while self.peek().is_alphabetic() || self.peek().is_digit(10) || self.peek() == '_' {
+        while self.peek().is_alphabetic() || self.peek().is_ascii_digit() || self.peek() == '_' {
             self.advance();
         }
 
@@ -191,12 +315,14 @@ impl<'a> Scanner<'a> {
     }
 
     fn is_at_end(&self) -> bool {
-        self.start == self.source.len()
+        self.current >= self.source.len()
     }
 
     pub fn advance(&mut self) -> char {
-        self.current += 1;
-        self.source[self.current - 1..].chars().next().unwrap()
+        let c = self.peek();
+        // Step over the whole character, comments and strings may hold any text
+        self.current += c.len_utf8();
+        c
     }
 
     fn next_matches(&mut self, expected: char) -> bool {
@@ -208,10 +334,11 @@ impl<'a> Scanner<'a> {
         }
 
         self.current += 1;
-        return true;
+        true
     }
 
-    fn skip_whitespace_and_comments(&mut self) {
+    /// Returns an error token if a block comment is never closed
+    fn skip_whitespace_and_comments(&mut self) -> Option<Token> {
         loop {
             let c = self.peek();
             match c {
@@ -222,30 +349,59 @@ impl<'a> Scanner<'a> {
                     self.line += 1;
                     self.advance();
                 }
-                '/' => {
-                    if self.peek_next() == '/' {
-                        while self.peek() != '\n' && !self.is_at_end() {
-                            self.advance();
-                        }
-                    } else {
-                        return ();
+                '/' if self.peek_next() == '/' && !self.is_doc_comment(self.current) => {
+                    while self.peek() != '\n' && !self.is_at_end() {
+                        self.advance();
                     }
                 }
-                _ => return (),
+                '/' if self.peek_next() == '*' => {
+                    self.start = self.current;
+                    if !self.skip_block_comment() {
+                        return Some(self.error_token(UNTERMINATED_BLOCK_COMMENT));
+                    }
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// Skips a `/* */` comment, which may contain other block comments
+    fn skip_block_comment(&mut self) -> bool {
+        let mut depth = 0;
+        loop {
+            if self.is_at_end() {
+                return false;
+            }
+            if self.peek() == '/' && self.peek_next() == '*' {
+                self.current += 2;
+                depth += 1;
+            } else if self.peek() == '*' && self.peek_next() == '/' {
+                self.current += 2;
+                depth -= 1;
+                if depth == 0 {
+                    return true;
+                }
+            } else {
+                if self.peek() == '\n' {
+                    self.line += 1;
+                }
+                self.advance();
             }
         }
     }
 
+    /// Whether a doc comment starts at `offset`, `////` is an ordinary comment
+    fn is_doc_comment(&self, offset: usize) -> bool {
+        let rest = &self.source[offset..];
+        rest.starts_with("///") && !rest.starts_with("////")
+    }
+
     fn peek(&self) -> char {
         self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.source[self.current + 1..].chars().next().unwrap()
-        }
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 }
 
@@ -257,6 +413,8 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -264,6 +422,11 @@ pub enum TokenType {
     Semicolon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
 
     // One- or two- character tokens
     Bang,
@@ -274,11 +437,17 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    TildeSlash,
+    StarStar,
+    LessLess,
+    GreaterGreater,
 
     // Literals
     Identifier,
     String,
+    Interpolation,
     Number,
+    DocComment,
 
     // Keywords
     And,
@@ -308,6 +477,8 @@ pub struct Token {
     pub type_: TokenType,
     pub name: String,
     pub line: usize,
+    pub start: usize, // Offset of the first byte of the lexeme in the source
+    pub end: usize,   // Offset one past the last byte of the lexeme
 }
 
 impl Token {
@@ -316,12 +487,129 @@ impl Token {
             type_: TokenType::DefaultConstructed,
             name: "Default constructed Token".to_string(),
             line: 0,
+            start: 0,
+            end: 0,
         }
     }
 }
 
+impl Default for Token {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?} {}", self.type_, self.name)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<(TokenType, String)> {
+        let mut scanner = Scanner::new(source);
+        let mut tokens = Vec::new();
+        loop {
+            let token = scanner.scan_token();
+            if token.type_ == TokenType::Eof {
+                return tokens;
+            }
+            tokens.push((token.type_, token.name));
+        }
+    }
+
+    fn token(type_: TokenType, name: &str) -> (TokenType, String) {
+        (type_, name.to_string())
+    }
+
+    #[test]
+    fn nested_interpolations() {
+        use TokenType::*;
+        assert_eq!(
+            tokens(r#""a ${ "b ${1}" } c""#),
+            [
+                token(Interpolation, "\"a ${"),
+                token(Interpolation, "\"b ${"),
+                token(Number, "1"),
+                token(String, "}\""),
+                token(String, "} c\""),
+            ]
+        );
+        // Braces inside the expression don't end it
+        assert_eq!(
+            tokens(r#""${ { } }""#),
+            [
+                token(Interpolation, "\"${"),
+                token(LeftBrace, "{"),
+                token(RightBrace, "}"),
+                token(String, "}\""),
+            ]
+        );
+        assert_eq!(
+            tokens(r#""${}""#),
+            [token(Interpolation, "\"${"), token(String, "}\"")]
+        );
+    }
+
+    #[test]
+    fn escaped_interpolations_are_part_of_the_string() {
+        assert_eq!(
+            tokens(r#""\${x}""#),
+            [token(TokenType::String, r#""\${x}""#)]
+        );
+    }
+
+    #[test]
+    fn unfinished_interpolations_are_incomplete() {
+        assert!(Scanner::is_incomplete(r#""${"#));
+        assert!(Scanner::is_incomplete(r#""a ${1"#));
+        assert!(Scanner::is_incomplete(r#""a ${1}"#));
+        assert!(Scanner::is_incomplete(r#""a ${ "b ${1}" "#));
+        assert!(!Scanner::is_incomplete(r#""a ${ "b ${1}" }""#));
+        assert!(!Scanner::is_incomplete(r#""\${""#));
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        use TokenType::*;
+        assert_eq!(tokens("/* a /* b */ c */ 1"), [token(Number, "1")]);
+        assert_eq!(
+            tokens("1 /**/ /* */ 2"),
+            [token(Number, "1"), token(Number, "2")]
+        );
+        assert_eq!(tokens("/* a */ */"), [token(Star, "*"), token(Slash, "/")]);
+    }
+
+    #[test]
+    fn lines_are_counted_inside_block_comments() {
+        let mut scanner = Scanner::new("/*\n/*\n*/\n*/ x\ny");
+        assert_eq!(scanner.scan_token().line, 4);
+        assert_eq!(scanner.scan_token().line, 5);
+    }
+
+    #[test]
+    fn unterminated_block_comments() {
+        use TokenType::*;
+        let unterminated = token(Error, UNTERMINATED_BLOCK_COMMENT);
+        assert_eq!(tokens("1 /* a"), [token(Number, "1"), unterminated.clone()]);
+        assert_eq!(tokens("/* /* */ 1"), [unterminated]);
+        assert!(Scanner::is_incomplete("/* /* */ 1"));
+        assert!(!Scanner::is_incomplete("/* /* */ */ 1"));
+    }
+
+    #[test]
+    fn only_three_slashes_start_a_doc_comment() {
+        use TokenType::*;
+        assert_eq!(
+            tokens("/// doc\n//// not doc\n// comment\n1 /// trailing"),
+            [
+                token(DocComment, "/// doc"),
+                token(Number, "1"),
+                token(DocComment, "/// trailing"),
+            ]
+        );
+    }
+}
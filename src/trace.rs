@@ -0,0 +1,56 @@
+use std::io::{self, Write};
+
+/// The environment variable `Trace::enable` specs can be read from
+pub const TRACE_ENV_VAR: &str = "KURISU_TRACE";
+
+/// Controls what the VM reports about the code it runs, and where to.
+/// Tracing the stack or the executed instructions needs the `trace` feature.
+pub struct Trace {
+    pub code: bool,         // Disassemble chunks once they're compiled
+    pub stack: bool,        // Show the stack before every instruction
+    pub instructions: bool, // Show every instruction before it's executed
+    pub sink: Box<dyn Write + Send>,
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self::new(Box::new(io::stderr()))
+    }
+}
+
+impl Trace {
+    /// Creates a configuration with everything turned off
+    pub fn new(sink: Box<dyn Write + Send>) -> Self {
+        Trace {
+            code: false,
+            stack: false,
+            instructions: false,
+            sink,
+        }
+    }
+
+    /// Turns on the options listed in `spec`, a comma separated list of
+    /// `code`, `stack`, `instructions` or `all`
+    pub fn enable(&mut self, spec: &str) -> Result<(), String> {
+        for option in spec.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            match option {
+                "code" => self.code = true,
+                "stack" => self.stack = true,
+                "instructions" => self.instructions = true,
+                "all" => {
+                    self.code = true;
+                    self.stack = true;
+                    self.instructions = true;
+                }
+                _ => return Err(format!("Unknown trace option '{}'", option)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes to the sink with `write`. Tracing is best effort, a broken sink shouldn't stop
+    /// the script, so errors are ignored
+    pub(crate) fn emit(&mut self, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) {
+        let _ = write(&mut self.sink);
+    }
+}
@@ -1,12 +1,14 @@
 use std::fmt;
 
+use crate::arithmetic;
 use crate::object::Object;
 
-#[derive(Debug, Clone, PartialOrd)]
+#[derive(Debug, Clone)]
 pub enum Value {
     Nil,
     Boolean(bool),
     Number(f64),
+    Int(i64),
     Obj(Box<Object>),
 }
 
@@ -16,6 +18,7 @@ impl PartialEq for Value {
             (Value::Nil, Value::Nil) => true,
             (Value::Boolean(a), Value::Boolean(b)) => a == b,
             (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
             (Value::Obj(a), Value::Obj(b)) => **a == **b,
             _ => false,
         }
@@ -23,6 +26,26 @@ impl PartialEq for Value {
 }
 
 impl Value {
+    pub fn nil() -> Self {
+        Self::Nil
+    }
+
+    pub fn boolean(b: bool) -> Self {
+        Self::Boolean(b)
+    }
+
+    pub fn number(n: f64) -> Self {
+        Self::Number(n)
+    }
+
+    pub fn int(i: i64) -> Self {
+        Self::Int(i)
+    }
+
+    pub fn object(obj: Object) -> Self {
+        Self::Obj(Box::new(obj))
+    }
+
     pub fn is_falsey(&self) -> bool {
         match self {
             Self::Boolean(b) => !b,
@@ -31,13 +54,25 @@ impl Value {
         }
     }
 
-    pub fn is_number(&self) -> bool {
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Self::Nil)
+    }
+
+    pub fn is_bool(&self) -> bool {
+        matches!(self, Self::Boolean(_))
+    }
+
+    pub fn as_bool(&self) -> bool {
         match self {
-            Self::Number(_) => true,
-            _ => false,
+            Self::Boolean(b) => *b,
+            _ => panic!("self was not a boolean"),
         }
     }
 
+    pub fn is_number(&self) -> bool {
+        matches!(self, Self::Number(_))
+    }
+
     pub fn to_number(&self) -> f64 {
         match self {
             Self::Number(n) => *n,
@@ -45,6 +80,28 @@ impl Value {
         }
     }
 
+    pub fn is_int(&self) -> bool {
+        matches!(self, Self::Int(_))
+    }
+
+    pub fn as_int(&self) -> i64 {
+        match self {
+            Self::Int(i) => *i,
+            _ => panic!("self was not an int"),
+        }
+    }
+
+    pub fn is_object(&self) -> bool {
+        matches!(self, Self::Obj(_))
+    }
+
+    pub fn as_object(&self) -> &Object {
+        match self {
+            Self::Obj(obj) => obj,
+            _ => panic!("self was not an Object"),
+        }
+    }
+
     pub fn is_string(&self) -> bool {
         match self {
             Value::Obj(obj) => obj.is_string(),
@@ -53,10 +110,7 @@ impl Value {
     }
 
     pub fn as_string(&self) -> &str {
-        match self {
-            Value::Obj(obj) => obj.as_string(),
-            _ => panic!("self was not an Object"),
-        }
+        self.as_object().as_string()
     }
 }
 
@@ -64,7 +118,8 @@ impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::Boolean(b) => write!(f, "{}", b),
-            Value::Number(n) => write!(f, "{}", n),
+            Value::Number(n) => arithmetic::write_float(f, *n),
+            Value::Int(i) => write!(f, "{}", i),
             Value::Nil => write!(f, "nil"),
             Value::Obj(obj) => write!(f, "{}", obj),
         }
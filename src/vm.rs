@@ -1,179 +1,562 @@
+use crate::arithmetic::{self, ArithOp};
 use crate::chunk::Chunk;
-use crate::compiler;
-use crate::object::Object;
+use crate::compiler::{self, CompileError};
+use crate::interrupt::InterruptHandle;
+use crate::natives::NATIVES;
+use crate::object::{NativeError, Object};
 use crate::opcode::OpCode;
+#[cfg(feature = "register-vm")]
+use crate::register_chunk::RegisterChunk;
+use crate::trace::Trace;
 use crate::value::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
+use std::io::{self, Write};
+use std::mem;
 
-const DEBUG_SHOW_DISASSEMBLY: bool = false;
-const DEBUG_SHOW_STACK: bool = true;
+#[cfg(feature = "register-vm")]
+mod registers;
 
 #[derive(Debug)]
 pub enum VMError {
     Compile,
     Runtime,
+    /// The instruction budget ran out, the script can be continued with `VM::resume`
+    OutOfFuel,
+    /// The script was stopped through an `InterruptHandle`
+    Interrupted,
+    /// The script called `exit` with the given code
+    Exit(i32),
+    /// `VM::resume` was called while no script was suspended
+    NothingToResume,
 }
 
 type VMStack = Vec<Value>;
 
+/// How many instructions are executed between checks for interruptions
+const INTERRUPT_CHECK_INTERVAL: u32 = 1024;
+
+/// One of the VM's dispatch loops, continuing from the current instruction
+type RunFn = fn(&mut VM) -> Result<Value, VMError>;
+
 pub struct VM {
     chunk: Chunk,
     ip: usize,
+    instruction: usize, // Where the instruction being executed starts, errors report its line
     stack: VMStack,
+    globals: HashMap<String, Value>, // Kept between scripts, so sessions can build on earlier input
+    builtins: HashMap<String, Value>, // The globals every script starts with
+    trace: Trace,
+    stdout: Box<dyn Write + Send>,
+    stderr: Box<dyn Write + Send>,
+    script_name: String,      // How runtime errors refer to the script
+    fuel: Option<u64>,        // How many more instructions may be executed, `None` meaning no limit
+    suspended: Option<RunFn>, // How to continue a script that ran out of fuel
+    returns_expression: bool, // Whether the running script's result should be shown
+    interrupt: InterruptHandle,
+    interrupt_countdown: u32, // Instructions left until the next check for interruptions
+    max_stack: Option<usize>,
+    max_heap: Option<usize>,
+    heap_bytes: usize, // Bytes allocated by the current script
+    #[cfg(feature = "register-vm")]
+    register_chunk: RegisterChunk,
 }
 
 impl fmt::Display for VMError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "An error occured {:?}", self)
+        let message = match self {
+            Self::Compile => "compile error",
+            Self::Runtime => "runtime error",
+            Self::OutOfFuel => "the instruction budget ran out",
+            Self::Interrupted => "the script was interrupted",
+            Self::NothingToResume => "there is no script to resume",
+            Self::Exit(code) => return write!(f, "the script exited with code {}", code),
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl VM {
     pub fn new() -> Self {
-        VM {
+        let mut vm = VM {
             chunk: Chunk::new(),
             ip: 0,
+            instruction: 0,
             stack: VMStack::new(),
+            globals: HashMap::new(),
+            builtins: HashMap::new(),
+            trace: Trace::default(),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            script_name: "script".to_string(),
+            fuel: None,
+            suspended: None,
+            returns_expression: false,
+            interrupt: InterruptHandle::new(),
+            interrupt_countdown: 0,
+            max_stack: None,
+            max_heap: None,
+            heap_bytes: 0,
+            #[cfg(feature = "register-vm")]
+            register_chunk: Default::default(),
+        };
+        for native in NATIVES.iter() {
+            vm.define_global(native.name, Value::object(Object::Native(*native)));
+        }
+        vm
+    }
+
+    /// Defines a global every script can use, unlike the globals scripts define
+    /// it's kept by `reset`
+    pub fn define_global(&mut self, name: &str, val: Value) {
+        self.builtins.insert(name.to_string(), val.clone());
+        self.globals.insert(name.to_string(), val);
+    }
+
+    /// Limits how many instructions may be executed before scripts stop with
+    /// `VMError::OutOfFuel`, `None` removes the limit
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// The number of instructions that may still be executed, `None` if there's no limit
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Allows `amount` more instructions to be executed, does nothing if there's no limit
+    pub fn add_fuel(&mut self, amount: u64) {
+        if let Some(fuel) = &mut self.fuel {
+            *fuel = fuel.saturating_add(amount);
         }
     }
 
+    /// Limits how many values the stack may hold, scripts going over it fail with a
+    /// "Stack overflow" runtime error. `None` removes the limit
+    pub fn set_max_stack(&mut self, max: Option<usize>) {
+        self.max_stack = max;
+    }
+
+    /// Limits how many bytes a script may allocate in total for strings, lists and, with
+    /// NaN-boxing, ints too large to store in place. Copying a value, e.g. reading a global,
+    /// counts as allocating it again. Scripts going over it fail with an "Out of memory"
+    /// runtime error. `None` removes the limit
+    pub fn set_max_heap(&mut self, max: Option<usize>) {
+        self.max_heap = max;
+    }
+
+    /// Returns a handle that can stop this VM's scripts from another thread
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Forgets the globals defined by scripts and any suspended script,
+    /// settings such as limits and tracing are kept
+    pub fn reset(&mut self) {
+        self.globals = self.builtins.clone();
+        self.stack.clear();
+        self.suspended = None;
+    }
+
+    /// Whether the last script ran out of fuel and can be continued with `resume`
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+
+    /// Sets where the results of scripts are written to
+    pub fn set_stdout(&mut self, stdout: Box<dyn Write + Send>) {
+        self.stdout = stdout;
+    }
+
+    /// Sets where compile and runtime errors are written to
+    pub fn set_stderr(&mut self, stderr: Box<dyn Write + Send>) {
+        self.stderr = stderr;
+    }
+
+    /// Sets how runtime errors refer to the script, e.g. by its path
+    pub fn set_script_name(&mut self, name: &str) {
+        self.script_name = name.to_string();
+    }
+
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = trace;
+    }
+
+    pub fn trace_mut(&mut self) -> &mut Trace {
+        &mut self.trace
+    }
+
+    /// Compiles and runs `source`. Globals persist between calls, and if the script ends with
+    /// an expression statement its value is shown and stored in the global `_`
     pub fn interpret(&mut self, source: &str) -> Result<(), VMError> {
-        match compiler::compile(source) {
-            Some(chunk) => {
-                self.chunk = chunk;
-                self.ip = 0;
-                self.run()
-            }
-            None => Err(VMError::Compile),
+        let result = self.as_one_run(|vm| vm.compile_and_run(source))?;
+        self.show_result(result);
+        Ok(())
+    }
+
+    /// Continues the script that last ran out of fuel, showing its result like `interpret` does.
+    /// Fails with `VMError::NothingToResume` if there's no such script, see `is_suspended`
+    pub fn resume(&mut self) -> Result<(), VMError> {
+        let run = self.suspended.take().ok_or(VMError::NothingToResume)?;
+        let result = self.as_one_run(run)?;
+        self.show_result(result);
+        Ok(())
+    }
+
+    /// Runs an already compiled chunk, showing its result like `interpret` does
+    pub fn interpret_chunk(&mut self, chunk: Chunk) -> Result<(), VMError> {
+        let result = self.as_one_run(|vm| {
+            vm.trace_code(|out| chunk.disassemble("code", out));
+            vm.execute(chunk)
+        })?;
+        self.show_result(result);
+        Ok(())
+    }
+
+    fn show_result(&mut self, result: Value) {
+        if !self.returns_expression {
+            return;
+        }
+
+        // The script already ran, failing to show its result isn't an error in the script
+        let _ = writeln!(self.stdout, "{}", result);
+        self.globals.insert("_".to_string(), result);
+    }
+
+    /// Runs `run` as a single script as far as interruptions are concerned, requests made
+    /// before it starts or after it finishes don't affect it. Nested calls share the outer run
+    fn as_one_run<T>(&mut self, run: impl FnOnce(&mut Self) -> T) -> T {
+        if self.interrupt.is_running() {
+            return run(self);
         }
+        self.interrupt.start_run();
+        let result = run(self);
+        self.interrupt.finish_run();
+        result
     }
 
-    fn run(&mut self) -> Result<(), VMError> {
-        loop {
-            if self.ip >= self.chunk.len() {
-                return Ok(());
+    fn compile_and_run(&mut self, source: &str) -> Result<Value, VMError> {
+        #[cfg(not(feature = "register-vm"))]
+        let (compiled, execute) = (compiler::compile(source), Self::execute);
+        #[cfg(feature = "register-vm")]
+        let (compiled, execute) = (compiler::compile_registers(source), Self::execute_registers);
+        match compiled {
+            Ok(chunk) => {
+                self.trace_code(|out| chunk.disassemble("code", out));
+                execute(self, chunk)
+            }
+            Err(errors) => {
+                self.report_compile_errors(&errors);
+                Err(VMError::Compile)
             }
+        }
+    }
 
-            if DEBUG_SHOW_STACK {
-                for val in &self.stack {
-                    print!("[{:?}]", val);
-                }
-                println!("");
+    /// Disassembles freshly compiled code if `Trace::code` is set
+    fn trace_code(&mut self, disassemble: impl FnOnce(&mut dyn Write) -> io::Result<()>) {
+        if self.trace.code {
+            self.trace.emit(disassemble);
+        }
+    }
+
+    /// Runs an already compiled chunk, returning the value it produced.
+    /// The chunk must end with an `OpCode::Return`, as that's the only way execution stops
+    pub fn execute(&mut self, chunk: Chunk) -> Result<Value, VMError> {
+        self.returns_expression = chunk.returns_expression;
+        self.chunk = chunk;
+        self.ip = 0;
+        self.stack.clear();
+        self.suspended = None;
+        self.interrupt_countdown = 0;
+        self.heap_bytes = 0;
+        self.as_one_run(Self::run)
+    }
+
+    fn run(&mut self) -> Result<Value, VMError> {
+        loop {
+            if !self.consume_fuel() {
+                self.suspended = Some(Self::run);
+                return Err(VMError::OutOfFuel);
             }
-            if DEBUG_SHOW_DISASSEMBLY {
-                print!("          ");
-                self.chunk.dissassemble_instruction(self.ip);
+            if self.is_interrupted() {
+                return Err(VMError::Interrupted);
             }
 
+            #[cfg(feature = "trace")]
+            self.trace_instruction();
+
+            self.instruction = self.ip;
             match self.chunk.next_byte(&mut self.ip).into() {
-                OpCode::Return => {
-                    println!("{}", self.pop());
-                    return Ok(());
-                }
+                OpCode::Return => return Ok(self.pop()),
                 OpCode::Constant => {
                     let val = self.chunk.get_constant(&mut self.ip, false).clone();
-                    self.push(val);
+                    self.allocate(heap_size(&val), self.chunk.lines[self.instruction])?;
+                    self.push(val)?;
                 }
                 OpCode::ConstantLong => {
                     let val = self.chunk.get_constant(&mut self.ip, true).clone();
-                    self.push(val);
+                    self.allocate(heap_size(&val), self.chunk.lines[self.instruction])?;
+                    self.push(val)?;
                 }
-                OpCode::Nil => self.push(Value::Nil),
-                OpCode::True => self.push(Value::Boolean(true)),
-                OpCode::False => self.push(Value::Boolean(false)),
+                OpCode::Nil => self.push(Value::nil())?,
+                OpCode::True => self.push(Value::boolean(true))?,
+                OpCode::False => self.push(Value::boolean(false))?,
                 OpCode::Negate => {
-                    if self.peek(0).is_number() {
-                        let a = self.pop().to_number();
-                        self.push(Value::Number(-a))
-                    }
+                    let val = arithmetic::negate(self.peek(0));
+                    self.replace_operands(1, val)?;
+                }
+                OpCode::BitNot => {
+                    let val = arithmetic::bit_not(self.peek(0));
+                    self.replace_operands(1, val)?;
                 }
                 OpCode::Not => {
                     let val = self.pop().is_falsey();
-                    self.push(Value::Boolean(val))
+                    self.push(Value::boolean(val))?;
+                }
+                OpCode::ToString => {
+                    let val = to_string(self.peek(0));
+                    self.allocate(heap_size(&val), self.chunk.lines[self.instruction])?;
+                    self.pop();
+                    self.push(val)?;
                 }
                 OpCode::Add => {
                     let b = self.peek(0);
                     let a = self.peek(1);
 
                     if a.is_string() && b.is_string() {
+                        let len = a.as_string().len() + b.as_string().len();
+                        self.allocate(len, self.chunk.lines[self.instruction])?;
                         let b = self.pop();
                         let a = self.pop();
                         let new = format!("{}{}", a.as_string(), b.as_string());
-                        self.push(Value::Obj(Box::new(Object::String(new))));
-                    } else if a.is_number() && b.is_number() {
-                        let b = self.pop().to_number();
-                        let a = self.pop().to_number();
-                        self.push(Value::Number(a + b));
+                        self.push(Value::object(Object::String(new)))?;
                     } else {
+                        self.arithmetic(ArithOp::Add)?;
                     }
                 }
-                OpCode::Subtract => match (self.peek(0), self.peek(1)) {
-                    (Value::Number(_), Value::Number(_)) => {
-                        let b = self.pop().to_number();
-                        let a = self.pop().to_number();
-                        self.push(Value::Number(a - b));
-                    }
-                    _ => {
-                        self.runtime_error("Operands must be numbers");
-                        return Err(VMError::Runtime);
-                    }
-                },
-                OpCode::Multiply => match (self.peek(0), self.peek(1)) {
-                    (Value::Number(_), Value::Number(_)) => {
-                        let b = self.pop().to_number();
-                        let a = self.pop().to_number();
-                        self.push(Value::Number(a * b));
-                    }
-                    _ => {
-                        self.runtime_error("Operands must be numbers");
-                        return Err(VMError::Runtime);
-                    }
-                },
-                OpCode::Divide => match (self.peek(0), self.peek(1)) {
-                    (Value::Number(_), Value::Number(_)) => {
-                        let b = self.pop().to_number();
-                        let a = self.pop().to_number();
-                        self.push(Value::Number(a / b));
-                    }
-                    _ => {
-                        self.runtime_error("Operands must be numbers");
-                        return Err(VMError::Runtime);
-                    }
-                },
+                OpCode::Subtract => self.arithmetic(ArithOp::Subtract)?,
+                OpCode::Multiply => self.arithmetic(ArithOp::Multiply)?,
+                OpCode::Divide => self.arithmetic(ArithOp::Divide)?,
+                OpCode::FloorDivide => self.arithmetic(ArithOp::FloorDivide)?,
+                OpCode::Modulo => self.arithmetic(ArithOp::Modulo)?,
+                OpCode::Power => self.arithmetic(ArithOp::Power)?,
+                OpCode::BitAnd => self.arithmetic(ArithOp::BitAnd)?,
+                OpCode::BitOr => self.arithmetic(ArithOp::BitOr)?,
+                OpCode::BitXor => self.arithmetic(ArithOp::BitXor)?,
+                OpCode::ShiftLeft => self.arithmetic(ArithOp::ShiftLeft)?,
+                OpCode::ShiftRight => self.arithmetic(ArithOp::ShiftRight)?,
                 OpCode::Equal => {
                     let b = self.pop();
                     let a = self.pop();
-                    self.push(Value::Boolean(a == b));
+                    self.push(Value::boolean(arithmetic::equal(&a, &b)))?;
                 }
-                OpCode::Greater => match (self.peek(0), self.peek(1)) {
-                    (Value::Number(_), Value::Number(_)) => {
-                        let b = self.pop();
-                        let a = self.pop();
-                        self.push(Value::Boolean(a > b));
-                    }
-                    _ => {
-                        self.runtime_error("Operands must be numbers");
-                        return Err(VMError::Runtime);
-                    }
-                },
-                OpCode::Less => match (self.peek(0), self.peek(1)) {
-                    (Value::Number(_), Value::Number(_)) => {
-                        let b = self.pop();
-                        let a = self.pop();
-                        self.push(Value::Boolean(a < b));
+                OpCode::NotEqual => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(Value::boolean(!arithmetic::equal(&a, &b)))?;
+                }
+                // `>=` and `<=` are true for NaNs, to match the `Less; Not` and `Greater; Not`
+                // sequences they replace
+                OpCode::Greater => self.comparison(|ord| ord == Some(Ordering::Greater))?,
+                OpCode::Less => self.comparison(|ord| ord == Some(Ordering::Less))?,
+                OpCode::GreaterEqual => self.comparison(|ord| ord != Some(Ordering::Less))?,
+                OpCode::LessEqual => self.comparison(|ord| ord != Some(Ordering::Greater))?,
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.chunk.get_constant(&mut self.ip, true).as_string();
+                    let val = self.stack.pop().unwrap();
+                    self.globals.insert(name.to_string(), val);
+                }
+                OpCode::GetGlobal => {
+                    let name = self.chunk.get_constant(&mut self.ip, true).as_string();
+                    match self.globals.get(name) {
+                        Some(val) => {
+                            let val = val.clone();
+                            self.allocate(heap_size(&val), self.chunk.lines[self.instruction])?;
+                            self.push(val)?;
+                        }
+                        None => {
+                            let msg = format!("Undefined variable '{}'", name);
+                            self.runtime_error(&msg);
+                            return Err(VMError::Runtime);
+                        }
                     }
-                    _ => {
-                        self.runtime_error("Operands must be numbers");
-                        return Err(VMError::Runtime);
+                }
+                OpCode::Call => {
+                    let argc = self.chunk.next_byte(&mut self.ip) as usize;
+                    let slot = self.stack.len() - 1 - argc;
+                    let result = self.call(slot, argc, self.chunk.lines[self.instruction])?;
+                    self.stack.truncate(slot);
+                    self.push(result)?;
+                }
+                OpCode::Index => {
+                    let line = self.chunk.lines[self.instruction];
+                    let val = match index(self.peek(1), self.peek(0)) {
+                        Ok(val) => val,
+                        Err(msg) => {
+                            self.report_error(msg, line);
+                            return Err(VMError::Runtime);
+                        }
+                    };
+                    self.allocate(heap_size(&val), line)?;
+                    self.pop();
+                    self.pop();
+                    self.push(val)?;
+                }
+                OpCode::SetGlobal => {
+                    let name = self.chunk.get_constant(&mut self.ip, true).as_string();
+                    match self.globals.get_mut(name) {
+                        Some(global) => {
+                            let val = self.stack.last().unwrap().clone();
+                            let size = heap_size(&val);
+                            *global = val;
+                            self.allocate(size, self.chunk.lines[self.instruction])?;
+                        }
+                        None => {
+                            let msg = format!("Undefined variable '{}'", name);
+                            self.runtime_error(&msg);
+                            return Err(VMError::Runtime);
+                        }
                     }
-                },
+                }
                 _ => return Err(VMError::Compile),
             }
         }
     }
 
-    fn push(&mut self, val: Value) {
+    #[cfg(feature = "trace")]
+    fn trace_instruction(&mut self) {
+        let (stack, chunk, ip) = (&self.stack, &self.chunk, self.ip);
+        let (show_stack, show_instruction) = (self.trace.stack, self.trace.instructions);
+        self.trace.emit(|out| {
+            if show_stack {
+                for val in stack {
+                    write!(out, "[{:?}]", val)?;
+                }
+                writeln!(out)?;
+            }
+            if show_instruction {
+                write!(out, "          ")?;
+                chunk.dissassemble_instruction(ip, out)?;
+            }
+            Ok(())
+        });
+    }
+
+    /// Takes the fuel needed to execute one instruction, returns `false` if there's none left
+    fn consume_fuel(&mut self) -> bool {
+        match &mut self.fuel {
+            Some(0) => false,
+            Some(fuel) => {
+                *fuel -= 1;
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// Checks for interruptions every `INTERRUPT_CHECK_INTERVAL` instructions, starting
+    /// with the first one executed. Calls check unconditionally, as should backward jumps.
+    fn is_interrupted(&mut self) -> bool {
+        if self.interrupt_countdown == 0 {
+            self.interrupt_countdown = INTERRUPT_CHECK_INTERVAL;
+            return self.interrupt.is_requested();
+        }
+        self.interrupt_countdown -= 1;
+        false
+    }
+
+    /// Calls the value at `slot` on the stack with the `argc` values above it as arguments
+    fn call(&mut self, slot: usize, argc: usize, line: u32) -> Result<Value, VMError> {
+        if self.interrupt.is_requested() {
+            return Err(VMError::Interrupted);
+        }
+        let callee = &self.stack[slot];
+        let native = match callee.is_object().then(|| callee.as_object()) {
+            Some(Object::Native(native)) => *native,
+            _ => {
+                self.report_error("Can only call functions", line);
+                return Err(VMError::Runtime);
+            }
+        };
+        if argc != native.arity {
+            let msg = format!("Expected {} arguments but got {}", native.arity, argc);
+            self.report_error(&msg, line);
+            return Err(VMError::Runtime);
+        }
+
+        match (native.function)(&self.stack[slot + 1..slot + 1 + argc]) {
+            Ok(val) => {
+                self.allocate(heap_size(&val), line)?;
+                Ok(val)
+            }
+            Err(NativeError::Runtime(msg)) => {
+                self.report_error(&msg, line);
+                Err(VMError::Runtime)
+            }
+            Err(NativeError::Exit(code)) => Err(VMError::Exit(code)),
+        }
+    }
+
+    /// Counts `bytes` against the heap limit, failing if the script went over it
+    fn allocate(&mut self, bytes: usize, line: u32) -> Result<(), VMError> {
+        self.heap_bytes = self.heap_bytes.saturating_add(bytes);
+        match self.max_heap {
+            Some(max) if self.heap_bytes > max => {
+                self.report_error("Out of memory", line);
+                Err(VMError::Runtime)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn push(&mut self, val: Value) -> Result<(), VMError> {
+        if self.max_stack.is_some_and(|max| self.stack.len() >= max) {
+            self.runtime_error("Stack overflow");
+            return Err(VMError::Runtime);
+        }
         self.stack.push(val);
+        Ok(())
+    }
+
+    /// Replaces the top `count` values of the stack with `result`, or reports its error
+    fn replace_operands(
+        &mut self,
+        count: usize,
+        result: Result<Value, &'static str>,
+    ) -> Result<(), VMError> {
+        match result {
+            Ok(val) => {
+                self.allocate(heap_size(&val), self.chunk.lines[self.instruction])?;
+                self.stack.truncate(self.stack.len() - count);
+                self.push(val)
+            }
+            Err(msg) => {
+                self.runtime_error(msg);
+                Err(VMError::Runtime)
+            }
+        }
+    }
+
+    fn arithmetic(&mut self, op: ArithOp) -> Result<(), VMError> {
+        let val = arithmetic::arithmetic(op, self.peek(1), self.peek(0));
+        self.replace_operands(2, val)
+    }
+
+    fn comparison(&mut self, test: fn(Option<Ordering>) -> bool) -> Result<(), VMError> {
+        let val =
+            arithmetic::compare(self.peek(1), self.peek(0)).map(|ord| Value::boolean(test(ord)));
+        self.replace_operands(2, val)
     }
 
     fn pop(&mut self) -> Value {
@@ -185,10 +568,288 @@ impl VM {
     }
 
     fn runtime_error(&mut self, msg: &str) {
-        eprintln!("{}", msg);
+        let line = self.chunk.lines[self.instruction];
+        self.report_error(msg, line);
+    }
+
+    /// Reporting is best effort, the error itself is still returned to the caller
+    fn report_error(&mut self, msg: &str, line: u32) {
+        let _ = writeln!(self.stderr, "{}", msg);
+        let _ = writeln!(self.stderr, "[line {}] in {}", line, self.script_name);
+    }
+
+    fn report_compile_errors(&mut self, errors: &[CompileError]) {
+        for e in errors {
+            let _ = writeln!(self.stderr, "{}", e);
+        }
+    }
+}
+
+/// How many bytes of heap memory `val` owns, including the contents of lists
+fn heap_size(val: &Value) -> usize {
+    #[cfg(feature = "nan-boxing")]
+    if val.is_boxed_int() {
+        return mem::size_of::<i64>();
+    }
+    if !val.is_object() {
+        return 0;
+    }
+    match val.as_object() {
+        Object::String(stri) => stri.len(),
+        Object::List(items) => {
+            items.len() * mem::size_of::<Value>() + items.iter().map(heap_size).sum::<usize>()
+        }
+        Object::Native(_) => 0,
+    }
+}
+
+/// Converts `val` to a string the way it would be printed, used by string interpolation
+fn to_string(val: &Value) -> Value {
+    if val.is_string() {
+        val.clone()
+    } else {
+        Value::object(Object::String(val.to_string()))
+    }
+}
+
+/// Reads `target[idx]`, the same way for both backends
+fn index(target: &Value, idx: &Value) -> Result<Value, &'static str> {
+    let items = match target.is_object().then(|| target.as_object()) {
+        Some(Object::List(items)) => items,
+        _ => return Err("Can only index lists"),
+    };
+    if !idx.is_int() {
+        return Err("Index must be an integer");
+    }
+    match usize::try_from(idx.as_int()) {
+        Ok(idx) if idx < items.len() => Ok(items[idx].clone()),
+        _ => Err("Index out of range"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Native;
+    use std::sync::{Arc, Mutex, OnceLock};
+
+    /// A sink whose contents can still be read after it's handed to the VM
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Captured {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn resuming_without_a_suspended_script_fails() {
+        let mut vm = VM::new();
+        assert!(matches!(vm.resume(), Err(VMError::NothingToResume)));
+
+        vm.set_fuel(Some(1));
+        assert!(matches!(
+            vm.interpret("var x = 1;"),
+            Err(VMError::OutOfFuel)
+        ));
+        vm.add_fuel(10);
+        vm.resume().unwrap();
+        assert!(matches!(vm.resume(), Err(VMError::NothingToResume)));
+    }
+
+    #[test]
+    fn interrupts_only_stop_the_running_script() {
+        let mut vm = VM::new();
+        vm.interrupt_handle().interrupt();
+        vm.interpret("var x = 1;").unwrap();
+
+        vm.set_fuel(Some(1));
+        assert!(matches!(
+            vm.interpret("var x = 1;\nvar y = x;"),
+            Err(VMError::OutOfFuel)
+        ));
+        vm.interrupt_handle().interrupt();
+        vm.add_fuel(10);
+        vm.resume().unwrap();
+    }
+
+    #[test]
+    fn runtime_errors_report_the_line_of_the_failing_instruction() {
+        for (source, line) in [
+            ("var s = \"a\";\n(-s)\n+ 1", 2),
+            ("var s = \"a\";\nvar t = s\n* 2;", 3),
+        ] {
+            let stderr = Captured::default();
+            let mut vm = VM::new();
+            vm.set_stderr(Box::new(stderr.clone()));
+            assert!(matches!(vm.interpret(source), Err(VMError::Runtime)));
+            let expected = format!("[line {}] in script", line);
+            assert!(
+                stderr.contents().contains(&expected),
+                "{}",
+                stderr.contents()
+            );
+        }
+    }
+
+    #[test]
+    fn interrupts_while_compiling_stop_the_script() {
+        /// A trace sink that interrupts the VM once the code has been compiled
+        struct InterruptOnTrace(InterruptHandle);
+
+        impl Write for InterruptOnTrace {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.interrupt();
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut vm = VM::new();
+        let mut trace = Trace::new(Box::new(InterruptOnTrace(vm.interrupt_handle())));
+        trace.code = true;
+        vm.set_trace(trace);
+        assert!(matches!(
+            vm.interpret("var x = 1;"),
+            Err(VMError::Interrupted)
+        ));
+
+        let chunk = compiler::compile("var x = 1;").unwrap();
+        assert!(matches!(
+            vm.interpret_chunk(chunk),
+            Err(VMError::Interrupted)
+        ));
+
+        vm.trace_mut().code = false;
+        vm.interpret("var x = 1;").unwrap();
+    }
+
+    #[test]
+    fn calls_check_for_interrupts() {
+        static HANDLE: OnceLock<InterruptHandle> = OnceLock::new();
+        fn interrupt(_: &[Value]) -> Result<Value, NativeError> {
+            HANDLE.get().unwrap().interrupt();
+            Ok(Value::nil())
+        }
+
+        let mut vm = VM::new();
+        let _ = HANDLE.set(vm.interrupt_handle());
+        let native = Native {
+            name: "interrupt",
+            arity: 0,
+            function: interrupt,
+        };
+        vm.define_global("interrupt", Value::object(Object::Native(native)));
+        let result = vm.interpret("interrupt();\nvar n = len(\"abc\");");
+        assert!(matches!(result, Err(VMError::Interrupted)));
+        assert!(matches!(vm.interpret("n"), Err(VMError::Runtime)));
+    }
+
+    fn list() -> Value {
+        let items = ["a", "bc", "def"].iter();
+        let items = items.map(|&s| Value::object(Object::String(s.to_string())));
+        Value::object(Object::List(items.collect()))
+    }
+
+    /// Runs `source` and returns the errors it reported
+    fn run_with_limits(source: &str, max_stack: Option<usize>, max_heap: Option<usize>) -> String {
+        let stderr = Captured::default();
+        let mut vm = VM::new();
+        vm.set_stderr(Box::new(stderr.clone()));
+        vm.set_max_stack(max_stack);
+        vm.set_max_heap(max_heap);
+        vm.define_global("list", list());
+        let _ = vm.interpret(source);
+        stderr.contents()
+    }
+
+    #[test]
+    fn scripts_stay_within_the_stack_limit() {
+        let source = "var a = 1;\na + (a + (a + (a + a)))";
+        assert_eq!(run_with_limits(source, Some(16), None), "");
+        let errors = run_with_limits(source, Some(3), None);
+        assert!(errors.starts_with("Stack overflow\n"), "{}", errors);
+    }
+
+    #[cfg(feature = "register-vm")]
+    #[test]
+    fn register_chunks_needing_too_many_registers_are_not_run() {
+        let source = "\n\nvar a = 1;\na + (a + (a + (a + a)))";
+        let chunk = compiler::compile_registers(source).unwrap();
+        let errors = run_with_limits(source, Some(chunk.registers - 1), None);
+        // Reported before anything runs, with the line of the first instruction
+        assert_eq!(errors, "Stack overflow\n[line 3] in script\n");
+        assert_eq!(run_with_limits(source, Some(chunk.registers), None), "");
+    }
+
+    #[test]
+    fn scripts_stay_within_the_heap_limit() {
+        let source = "var s = \"abcd\";\nvar t = s + s;";
+        assert_eq!(run_with_limits(source, None, Some(64)), "");
+        let errors = run_with_limits(source, None, Some(10));
+        assert!(errors.starts_with("Out of memory\n"), "{}", errors);
+    }
+
+    #[test]
+    fn copies_of_lists_count_their_items() {
+        let size = heap_size(&list());
+        assert_eq!(size, 3 * mem::size_of::<Value>() + 6);
+
+        let source = "var a = list;\nvar b = list;\nvar c = list;";
+        assert_eq!(run_with_limits(source, None, Some(6 * size)), "");
+        let errors = run_with_limits(source, None, Some(size));
+        assert!(errors.starts_with("Out of memory\n"), "{}", errors);
+    }
+
+    #[cfg(feature = "nan-boxing")]
+    #[test]
+    fn boxed_ints_count_against_the_heap_limit() {
+        let source = "var a = 1 << 60;\nvar b = a + 1;\nvar c = b;";
+        assert_eq!(run_with_limits(source, None, Some(64)), "");
+        let errors = run_with_limits(source, None, Some(8));
+        assert!(errors.starts_with("Out of memory\n"), "{}", errors);
+        assert_eq!(
+            run_with_limits("var a = 1 << 40;\nvar b = a;", None, Some(0)),
+            ""
+        );
+    }
+
+    #[test]
+    fn vm_can_move_between_threads() {
+        fn assert_send<T: Send>() {}
+        assert_send::<VM>();
+    }
+
+    #[test]
+    fn results_and_errors_go_to_the_configured_sinks() {
+        let (stdout, stderr) = (Captured::default(), Captured::default());
+        let mut vm = VM::new();
+        vm.set_stdout(Box::new(stdout.clone()));
+        vm.set_stderr(Box::new(stderr.clone()));
+
+        vm.interpret("var x = 20;").unwrap();
+        vm.interpret("x + 22").unwrap();
+        assert_eq!(stdout.contents(), "42\n");
 
-        let instruction = self.ip;
-        let line = self.chunk.lines[instruction];
-        eprintln!("[line {}] in script", line);
+        assert!(matches!(
+            vm.interpret("var s = \"a\"; -s"),
+            Err(VMError::Runtime)
+        ));
+        assert!(stderr.contents().contains("Operand must be a number"));
+        assert_eq!(stdout.contents(), "42\n");
     }
 }
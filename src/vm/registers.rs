@@ -0,0 +1,240 @@
+use super::{heap_size, index, to_string, VMError, VM};
+use crate::arithmetic::{self, ArithOp};
+use crate::object::Object;
+use crate::register_chunk::{Instruction, Operand, RegisterChunk};
+use crate::value::Value;
+use std::cmp::Ordering;
+
+impl VM {
+    /// Runs a chunk produced by the register backend, the value stack is used as its registers
+    pub fn execute_registers(&mut self, chunk: RegisterChunk) -> Result<Value, VMError> {
+        self.stack.clear();
+        self.suspended = None;
+        if self.max_stack.is_some_and(|max| chunk.registers > max) {
+            self.report_error("Stack overflow", chunk.lines.first().copied().unwrap_or(0));
+            return Err(VMError::Runtime);
+        }
+        self.stack.resize(chunk.registers, Value::nil());
+        self.returns_expression = chunk.returns_expression;
+        self.register_chunk = chunk;
+        self.ip = 0;
+        self.interrupt_countdown = 0;
+        self.heap_bytes = 0;
+        self.as_one_run(Self::run_registers)
+    }
+
+    fn run_registers(&mut self) -> Result<Value, VMError> {
+        // Moved out for the duration of the run, so registers can be written while it's borrowed
+        let chunk = std::mem::take(&mut self.register_chunk);
+        let result = self.run_register_chunk(&chunk);
+        self.register_chunk = chunk;
+        result
+    }
+
+    fn run_register_chunk(&mut self, chunk: &RegisterChunk) -> Result<Value, VMError> {
+        while self.ip < chunk.code.len() {
+            if !self.consume_fuel() {
+                self.suspended = Some(Self::run_registers);
+                return Err(VMError::OutOfFuel);
+            }
+            if self.is_interrupted() {
+                return Err(VMError::Interrupted);
+            }
+
+            #[cfg(feature = "trace")]
+            self.trace_register_instruction(chunk, self.ip);
+
+            let ip = self.ip;
+            self.ip += 1;
+
+            let (dst, val) = match chunk.code[ip] {
+                Instruction::Return { src } => {
+                    let val = self.read(chunk, src).clone();
+                    self.allocate(heap_size(&val), chunk.lines[ip])?;
+                    return Ok(val);
+                }
+                Instruction::Negate { dst, src } => {
+                    let result = arithmetic::negate(self.read(chunk, src));
+                    (dst, self.arithmetic_result(result, chunk.lines[ip])?)
+                }
+                Instruction::BitNot { dst, src } => {
+                    let result = arithmetic::bit_not(self.read(chunk, src));
+                    (dst, self.arithmetic_result(result, chunk.lines[ip])?)
+                }
+                Instruction::Not { dst, src } => {
+                    (dst, Value::boolean(self.read(chunk, src).is_falsey()))
+                }
+                Instruction::ToString { dst, src } => {
+                    let val = to_string(self.read(chunk, src));
+                    self.allocate(heap_size(&val), chunk.lines[ip])?;
+                    (dst, val)
+                }
+                Instruction::Add { dst, lhs, rhs } => {
+                    let (a, b) = (self.read(chunk, lhs), self.read(chunk, rhs));
+                    if a.is_string() && b.is_string() {
+                        let len = a.as_string().len() + b.as_string().len();
+                        let new = format!("{}{}", a.as_string(), b.as_string());
+                        self.allocate(len, chunk.lines[ip])?;
+                        (dst, Value::object(Object::String(new)))
+                    } else {
+                        let result = arithmetic::arithmetic(ArithOp::Add, a, b);
+                        (dst, self.arithmetic_result(result, chunk.lines[ip])?)
+                    }
+                }
+                Instruction::DefineGlobal { name, src } => {
+                    let val = self.read(chunk, src).clone();
+                    self.allocate(heap_size(&val), chunk.lines[ip])?;
+                    let name = chunk.constants[name as usize].as_string();
+                    self.globals.insert(name.to_string(), val);
+                    continue;
+                }
+                Instruction::GetGlobal { dst, name } => {
+                    let name = chunk.constants[name as usize].as_string();
+                    match self.globals.get(name) {
+                        Some(val) => {
+                            let val = val.clone();
+                            self.allocate(heap_size(&val), chunk.lines[ip])?;
+                            (dst, val)
+                        }
+                        None => {
+                            let msg = format!("Undefined variable '{}'", name);
+                            self.report_error(&msg, chunk.lines[ip]);
+                            return Err(VMError::Runtime);
+                        }
+                    }
+                }
+                Instruction::SetGlobal { name, src } => {
+                    let val = self.read(chunk, src).clone();
+                    let size = heap_size(&val);
+                    let name = chunk.constants[name as usize].as_string();
+                    match self.globals.get_mut(name) {
+                        Some(global) => *global = val,
+                        None => {
+                            let msg = format!("Undefined variable '{}'", name);
+                            self.report_error(&msg, chunk.lines[ip]);
+                            return Err(VMError::Runtime);
+                        }
+                    }
+                    self.allocate(size, chunk.lines[ip])?;
+                    continue;
+                }
+                Instruction::Move { dst, src } => {
+                    let val = self.read(chunk, src).clone();
+                    self.allocate(heap_size(&val), chunk.lines[ip])?;
+                    (dst, val)
+                }
+                Instruction::Call { dst, base, argc } => (
+                    dst,
+                    self.call(base as usize, argc as usize, chunk.lines[ip])?,
+                ),
+                Instruction::Index { dst, lhs, rhs } => {
+                    match index(self.read(chunk, lhs), self.read(chunk, rhs)) {
+                        Ok(val) => {
+                            self.allocate(heap_size(&val), chunk.lines[ip])?;
+                            (dst, val)
+                        }
+                        Err(msg) => {
+                            self.report_error(msg, chunk.lines[ip]);
+                            return Err(VMError::Runtime);
+                        }
+                    }
+                }
+                Instruction::Equal { dst, lhs, rhs } => (
+                    dst,
+                    Value::boolean(arithmetic::equal(
+                        self.read(chunk, lhs),
+                        self.read(chunk, rhs),
+                    )),
+                ),
+                Instruction::NotEqual { dst, lhs, rhs } => (
+                    dst,
+                    Value::boolean(!arithmetic::equal(
+                        self.read(chunk, lhs),
+                        self.read(chunk, rhs),
+                    )),
+                ),
+                instr => {
+                    let (dst, lhs, rhs) = instr.binary_operands().unwrap();
+                    let result = numeric(instr, self.read(chunk, lhs), self.read(chunk, rhs));
+                    (dst, self.arithmetic_result(result, chunk.lines[ip])?)
+                }
+            };
+            self.stack[dst as usize] = val;
+        }
+
+        Ok(Value::nil())
+    }
+
+    #[cfg(feature = "trace")]
+    fn trace_register_instruction(&mut self, chunk: &RegisterChunk, ip: usize) {
+        let stack = &self.stack;
+        let (show_stack, show_instruction) = (self.trace.stack, self.trace.instructions);
+        self.trace.emit(|out| {
+            if show_stack {
+                for (r, val) in stack.iter().enumerate() {
+                    write!(out, "[r{} {:?}]", r, val)?;
+                }
+                writeln!(out)?;
+            }
+            if show_instruction {
+                write!(out, "          ")?;
+                chunk.disassemble_instruction(ip, out)?;
+            }
+            Ok(())
+        });
+    }
+
+    /// The value computed by an arithmetic instruction, or its error reported
+    fn arithmetic_result(
+        &mut self,
+        result: Result<Value, &'static str>,
+        line: u32,
+    ) -> Result<Value, VMError> {
+        match result {
+            Ok(val) => {
+                self.allocate(heap_size(&val), line)?;
+                Ok(val)
+            }
+            Err(msg) => {
+                self.report_error(msg, line);
+                Err(VMError::Runtime)
+            }
+        }
+    }
+
+    fn read<'a>(&'a self, chunk: &'a RegisterChunk, operand: Operand) -> &'a Value {
+        match operand {
+            Operand::Register(r) => &self.stack[r as usize],
+            Operand::Constant(k) => &chunk.constants[k as usize],
+        }
+    }
+}
+
+/// Applies an instruction that only works on numbers,
+/// `>=` and `<=` are computed the same way as their stack based counterparts
+fn numeric(instr: Instruction, a: &Value, b: &Value) -> Result<Value, &'static str> {
+    let op = match instr {
+        Instruction::Subtract { .. } => ArithOp::Subtract,
+        Instruction::Multiply { .. } => ArithOp::Multiply,
+        Instruction::Divide { .. } => ArithOp::Divide,
+        Instruction::FloorDivide { .. } => ArithOp::FloorDivide,
+        Instruction::Modulo { .. } => ArithOp::Modulo,
+        Instruction::Power { .. } => ArithOp::Power,
+        Instruction::BitAnd { .. } => ArithOp::BitAnd,
+        Instruction::BitOr { .. } => ArithOp::BitOr,
+        Instruction::BitXor { .. } => ArithOp::BitXor,
+        Instruction::ShiftLeft { .. } => ArithOp::ShiftLeft,
+        Instruction::ShiftRight { .. } => ArithOp::ShiftRight,
+        _ => {
+            let ord = arithmetic::compare(a, b)?;
+            return Ok(Value::boolean(match instr {
+                Instruction::Greater { .. } => ord == Some(Ordering::Greater),
+                Instruction::GreaterEqual { .. } => ord != Some(Ordering::Less),
+                Instruction::Less { .. } => ord == Some(Ordering::Less),
+                Instruction::LessEqual { .. } => ord != Some(Ordering::Greater),
+                _ => unreachable!("not an arithmetic instruction"),
+            }));
+        }
+    };
+    arithmetic::arithmetic(op, a, b)
+}